@@ -6,7 +6,17 @@ const fileInput = $("#fileInput");
 const fileNameInput = $("#fileNameInput");
 const videoList = $("#videoList");
 
+// playlist id -> { name, description, videos, mode }
 const playlists = new Map();
+// video name -> preview manifest, fetched on first hover
+const previews = new Map();
+
+// playlist names can be anything
+function escapeHtml(text) {
+  const div = document.createElement("div");
+  div.textContent = text;
+  return div.innerHTML;
+}
 
 function getSettings() {
   const visualizer = $("#visualizer").value;
@@ -26,20 +36,26 @@ async function fetchPlaylists() {
   const playlistResponse = await response.json();
 
   playlists.clear();
-  playlistResponse.forEach(({ name, videos }) => playlists.set(name, videos));
+  playlistResponse.forEach((p) => playlists.set(p.id, p));
 
   $("#playlist").innerHTML = `
     <option value="none">select playlist</option>
     <option value="new">new</option>
     ${playlistResponse
       .map(
-        (p) =>
-          `<option value="${p.name}">${p.name.replaceAll("_", " ")}</option>`,
+        (p) => `<option value="id:${p.id}">${escapeHtml(p.name)}</option>`,
       )
       .join("")}
   `;
 }
 
+// the playlist's id, undefined for the 'none' & 'new' options
+// prefixed so a playlist whose id is 'new' or 'none' can't look like one of those
+function selectedPlaylistId() {
+  const value = $("#playlist").value;
+  return value.startsWith("id:") ? value.slice("id:".length) : undefined;
+}
+
 async function fetchVideos() {
   const response = await fetch("/videos");
   if (!response.ok) {
@@ -51,19 +67,26 @@ async function fetchVideos() {
   videoList.innerHTML = videos
     .map(
       (video) => `
-             <li class="video-item bg-black rounded-md shadow overflow-hidden relative" data-video="${video.name}">
+             <li class="video-item bg-black rounded-md shadow overflow-hidden relative" data-video="${video.name}" data-preview="${video.preview}">
                <div class="absolute top-2 left-2 z-10 video-select-parent hidden">
                  <label class="inline-flex items-center">
                    <input type="checkbox" class="video-select form-checkbox h-5 w-5 appearance-none border-2 border-purple-500 rounded-none bg-black checked:bg-purple-500 focus:outline-none focus:ring-2 focus:ring-purple-500 transition duration-200">
                  </label>
                </div>
                <div class="relative">
-                 <img src="/thumbs/${video.name_without_ext}.jpg" alt="${video.name}" class="w-full h-48 object-cover">
+                 <img src="${video.thumbnail}" alt="${video.name}" class="w-full h-48 object-cover">
+                 <div class="video-preview absolute inset-0 hidden bg-black bg-no-repeat"></div>
+                 <div class="absolute top-2 right-2 flex gap-1">
+                   ${video.root ? `<div class="px-1 bg-black bg-opacity-70 text-blue-400 text-xs uppercase" title="${video.read_only ? "read only library root" : "library root"}">${video.root}${video.read_only ? " (ro)" : ""}</div>` : ""}
+                   ${video.corrupt ? `<div class="px-1 bg-black bg-opacity-70 text-red-500 text-xs uppercase" title="failed a decode check, see /maintenance/decode">corrupt</div>` : ""}
+                   ${video.optimized === "copy" || video.optimized === "replaced" ? `<div class="px-1 bg-black bg-opacity-70 text-green-500 text-xs uppercase" title="has a playback optimized version">crt</div>` : ""}
+                 </div>
                  <div class="video-name absolute bottom-0 left-0 right-0 p-2 bg-black bg-opacity-70 text-purple-500 text-sm truncate">${video.name}</div>
                </div>
                <div class="flex divide-x-2 transition-all">
-                 <button class="video-button w-1/2 py-2 bg-green-500 hover:bg-green-600 text-black text-sm uppercase tracking-wider focus:outline-none focus:ring-2 focus:ring-green-500">Play</button>
-                 <button class="delete-button w-1/2 py-2 bg-red-500 hover:bg-red-600 text-black text-sm uppercase tracking-wider focus:outline-none focus:ring-2 focus:ring-red-500">Delete</button>
+                 <button class="video-button w-1/3 py-2 bg-green-500 hover:bg-green-600 text-black text-sm uppercase tracking-wider focus:outline-none focus:ring-2 focus:ring-green-500">Play</button>
+                 <button class="thumbnail-button w-1/3 py-2 bg-purple-500 hover:bg-purple-600 text-black text-sm uppercase tracking-wider focus:outline-none focus:ring-2 focus:ring-purple-500">Thumb</button>
+                 <button class="delete-button w-1/3 py-2 bg-red-500 hover:bg-red-600 text-black text-sm uppercase tracking-wider focus:outline-none focus:ring-2 focus:ring-red-500 disabled:opacity-50 disabled:cursor-not-allowed" ${video.read_only ? "disabled" : ""}>Delete</button>
                </div>
              </li>
            `,
@@ -107,11 +130,42 @@ async function uploadVideo(file, fileName) {
 
     await fetchVideos();
   } else {
-    notyf.error("Failed to upload video");
+    // 409 means a video with that name already exists
+    notyf.error(`Failed to upload video: ${await response.text()}`);
     console.error(response);
   }
 }
 
+// several files at once, each keeps its own name
+async function uploadVideos(files) {
+  const form = new FormData();
+  form.append("conflict", "rename");
+  for (const file of files) {
+    form.append("file", file);
+  }
+
+  const response = await fetch("/videos/multipart", {
+    method: "POST",
+    body: form,
+  });
+
+  if (!response.ok) {
+    notyf.error("Failed to upload videos");
+    console.error(response);
+    return;
+  }
+
+  const results = await response.json();
+  const failed = results.filter((r) => r.error);
+  // no file name if the upload itself broke off partway
+  failed.forEach((r) =>
+    notyf.error(r.file_name ? `${r.file_name}: ${r.error}` : r.error),
+  );
+
+  notyf.success(`Uploaded ${results.length - failed.length} videos`);
+  await fetchVideos();
+}
+
 async function deleteVideo(videoName) {
   const response = await fetch(`/videos`, {
     method: "DELETE",
@@ -120,7 +174,11 @@ async function deleteVideo(videoName) {
   });
 
   if (response.ok) {
-    notyf.success("Video deleted");
+    const item = await response.json();
+    // click the toast to undo
+    notyf
+      .success({ message: "Video moved to trash, click to undo", duration: 8000 })
+      .on("click", () => restoreVideo(item.id));
     fetchVideos();
   } else {
     notyf.error("Failed to delete video");
@@ -128,6 +186,76 @@ async function deleteVideo(videoName) {
   }
 }
 
+async function restoreVideo(id) {
+  const response = await fetch(`/trash/${id}/restore`, { method: "POST" });
+
+  if (response.ok) {
+    notyf.success("Video restored");
+    fetchVideos();
+  } else {
+    notyf.error(`Failed to restore video: ${await response.text()}`);
+  }
+}
+
+// '90' or '1:30' -> 90
+function parseTimestamp(input) {
+  return input
+    .split(":")
+    .reduce((total, part) => total * 60 + parseFloat(part), 0);
+}
+
+async function changeThumbnail(videoName) {
+  const input = prompt(
+    'Thumbnail time (e.g. 1:30), "auto" to let it pick, or "image" to upload one',
+  );
+  if (input === null || input.trim() === "") {
+    return;
+  }
+
+  if (input.trim() === "image") {
+    const picker = document.createElement("input");
+    picker.type = "file";
+    picker.accept = "image/*";
+    picker.addEventListener("change", async () => {
+      const [image] = picker.files;
+      if (!image) {
+        return;
+      }
+
+      const response = await fetch(
+        `/videos/thumbnail?video_name=${encodeURIComponent(videoName)}`,
+        { method: "POST", body: image },
+      );
+      await thumbnailResult(response);
+    });
+    picker.click();
+    return;
+  }
+
+  const timestamp = input.trim() === "auto" ? null : parseTimestamp(input.trim());
+  if (Number.isNaN(timestamp)) {
+    notyf.error("Invalid time");
+    return;
+  }
+
+  const response = await fetch("/videos/thumbnail", {
+    method: "PUT",
+    headers: { "Content-Type": "application/json" },
+    body: JSON.stringify({ video_name: videoName, timestamp }),
+  });
+  await thumbnailResult(response);
+}
+
+async function thumbnailResult(response) {
+  if (response.ok) {
+    notyf.success("Thumbnail updated");
+    fetchVideos();
+  } else {
+    notyf.error(`Failed to update thumbnail: ${await response.text()}`);
+    console.error(response);
+  }
+}
+
 async function playMedia(url) {
   const response = await fetch("/custom-media", {
     method: "PUT",
@@ -146,13 +274,36 @@ async function playMedia(url) {
   }
 }
 
-// pass in playlistName as undefined to shuffle all
-async function playPlaylist(playlistName) {
+// downloads into the library in the background, progress comes over /events
+async function saveMedia(url) {
+  const response = await fetch("/custom-media", {
+    method: "POST",
+    headers: { "Content-Type": "application/json" },
+    body: JSON.stringify({ url, max_height: 480 }),
+  });
+
+  if (response.ok) {
+    notyf.success("Downloading to library");
+  } else {
+    notyf.error(`Failed to save media: ${await response.text()}`);
+    console.error(response);
+  }
+}
+
+// undefined leaves it up to the playlist's own mode
+function getPlaybackMode() {
+  return $("#playbackMode").value || undefined;
+}
+
+// pass in playlistId as undefined to shuffle all
+async function playPlaylist(playlistId) {
   const response = await fetch("/playlists", {
     method: "PUT",
     headers: { "Content-Type": "application/json" },
     body: JSON.stringify({
-      playlist_name: playlistName,
+      playlist_id: playlistId,
+      // the picker is for playlists, shuffle all stays a shuffle
+      mode: playlistId ? getPlaybackMode() : undefined,
       ...getSettings(),
     }),
   });
@@ -163,24 +314,28 @@ async function playPlaylist(playlistName) {
   }
 }
 
-// videos = [] to delete
-async function savePlaylist(playlistName, videos) {
+// playlistId undefined for a new one, returns the saved playlist
+async function savePlaylist(playlistId, playlistName, description, videos) {
   const response = await fetch("/playlists", {
     method: "POST",
     headers: { "Content-Type": "application/json" },
     body: JSON.stringify({
+      playlist_id: playlistId,
       playlist_name: playlistName,
+      description,
       videos,
+      mode: getPlaybackMode(),
     }),
   });
 
-  if (response.ok) {
-    notyf.success("Playlist saved");
-    await fetchPlaylists();
-  } else {
-    notyf.error("Failed to save playlist");
-    console.error(response);
+  if (!response.ok) {
+    notyf.error(`Failed to save playlist: ${await response.text()}`);
+    return;
   }
+
+  notyf.success("Playlist saved");
+  await fetchPlaylists();
+  return await response.json();
 }
 
 // 0 = play/pause
@@ -204,6 +359,10 @@ $("#browseButton").addEventListener("click", () => fileInput.click());
 $("#uploadForm").addEventListener("submit", async (e) => {
   e.preventDefault();
 
+  if (fileInput.files.length > 1) {
+    return uploadVideos(fileInput.files);
+  }
+
   const file = fileInput.files[0];
   const fileName = fileNameInput.value;
 
@@ -213,9 +372,11 @@ $("#uploadForm").addEventListener("submit", async (e) => {
 });
 
 fileInput.addEventListener("change", (e) => {
-  const file = e.target.files[0];
-  if (file) {
-    fileNameInput.value = file.name;
+  const { files } = e.target;
+  if (files.length > 1) {
+    fileNameInput.value = `${files.length} files`;
+  } else if (files[0]) {
+    fileNameInput.value = files[0].name;
   }
 });
 
@@ -234,6 +395,51 @@ videoList.addEventListener("click", (e) => {
   if (classList.contains("delete-button")) {
     deleteVideo(videoName);
   }
+
+  if (classList.contains("thumbnail-button")) {
+    changeThumbnail(videoName);
+  }
+});
+
+// scrub through the sprite sheet by moving across the thumbnail
+videoList.addEventListener("mousemove", async (e) => {
+  const item = e.target.closest(".video-item");
+  const preview = e.target.closest(".video-preview, img")?.parentElement.querySelector(".video-preview");
+  if (!item || !preview || item.dataset.preview !== "true") {
+    return;
+  }
+
+  const name = item.dataset.video;
+  if (!previews.has(name)) {
+    previews.set(name, null);
+    const response = await fetch(`/videos/preview?video_name=${encodeURIComponent(name)}`);
+    previews.set(name, response.ok ? await response.json() : null);
+  }
+
+  const manifest = previews.get(name);
+  if (!manifest) {
+    return;
+  }
+
+  const bounds = preview.parentElement.getBoundingClientRect();
+  const index = Math.min(
+    manifest.frames.length - 1,
+    Math.floor(((e.clientX - bounds.left) / bounds.width) * manifest.frames.length),
+  );
+  const frame = manifest.frames[Math.max(0, index)];
+  const scale = bounds.height / manifest.frame_height;
+
+  preview.style.backgroundImage = `url("${manifest.sprite}")`;
+  preview.style.backgroundSize = `${manifest.columns * manifest.frame_width * scale}px auto`;
+  preview.style.backgroundPosition = `${(bounds.width - manifest.frame_width * scale) / 2 - frame.x * scale}px ${-frame.y * scale}px`;
+  preview.classList.remove("hidden");
+});
+
+videoList.addEventListener("mouseout", (e) => {
+  const preview = e.target.closest(".video-item")?.querySelector(".video-preview");
+  if (preview && !e.relatedTarget?.closest?.(".video-preview")) {
+    preview.classList.add("hidden");
+  }
 });
 
 $("#shuffleButton").addEventListener("click", async () => {
@@ -244,24 +450,16 @@ $("#shuffleButton").addEventListener("click", async () => {
 $("#stopButton").addEventListener("click", stopVideo);
 
 $("#playlist").addEventListener("change", async (e) => {
-  const playlistName = e.target.value;
-
-  $("#newPlaylistName").value = "";
-  selectVideosFromPlaylist("");
+  const playlistId = selectedPlaylistId();
+  const playlist = playlists.get(playlistId);
 
-  if (!playlistName || playlistName === "none") {
-    // if deselect, deselect all and hide new playlist section
-    return playlistUtilVisible(false);
-  }
-
-  playlistUtilVisible(true);
-  if (playlistName === "new") {
-    return;
-  }
+  $("#newPlaylistName").value = playlist?.name ?? "";
+  $("#playlistDescription").value = playlist?.description ?? "";
+  $("#playbackMode").value = playlist?.mode ?? "";
+  selectVideosFromPlaylist(playlistId);
 
-  // existing playlist, select and set name in case of changes
-  $("#newPlaylistName").value = playlistName.replaceAll("_", " ");
-  selectVideosFromPlaylist(playlistName);
+  // if deselect, deselect all and hide new playlist section
+  playlistUtilVisible(e.target.value !== "none");
 });
 
 function playlistUtilVisible(visible) {
@@ -275,26 +473,57 @@ function playlistUtilVisible(visible) {
 }
 
 $("#playPlaylistButton").addEventListener("click", async () => {
-  const playlistName = $("#playlist").value;
-  if (playlistName && playlistName !== "new" && playlistName !== "none") {
-    await playPlaylist(playlistName);
+  const playlistId = selectedPlaylistId();
+  if (playlists.has(playlistId)) {
+    await playPlaylist(playlistId);
   }
 });
 
+async function deletePlaylist(playlistId) {
+  const response = await fetch(
+    `/playlists/${encodeURIComponent(playlistId)}`,
+    { method: "DELETE" },
+  );
+
+  if (response.ok) {
+    notyf.success("Playlist deleted");
+    await fetchPlaylists();
+  } else {
+    notyf.error(`Failed to delete playlist: ${await response.text()}`);
+  }
+
+  return response.ok;
+}
+
 $("#savePlaylistButton").addEventListener("click", async () => {
   const playlistName = $("#newPlaylistName").value;
   if (!playlistName) {
     return;
   }
 
-  const videos = Array.from(
+  const selected = selectedPlaylistId();
+  const existing = playlists.get(selected)?.videos ?? [];
+  const checked = Array.from(
     document.querySelectorAll(".video-select:checked"),
   ).map((checkbox) => checkbox.closest(".video-item").dataset.video);
 
-  await savePlaylist(playlistName, videos);
-  await fetchPlaylists();
-
-  $("#playlist").value = playlistName.replaceAll(" ", "_");
+  // keeps the playlist's own order, newly checked videos go on the end
+  const videos = [
+    ...existing.filter((video) => checked.includes(video)),
+    ...checked.filter((video) => !existing.includes(video)),
+  ];
+
+  // renames it too if the name changed, the id stays the same
+  const saved = await savePlaylist(
+    playlists.has(selected) ? selected : undefined,
+    playlistName,
+    $("#playlistDescription").value,
+    videos,
+  );
+
+  if (saved && playlists.has(saved.id)) {
+    $("#playlist").value = `id:${saved.id}`;
+  }
 });
 
 $("#deselectButton").addEventListener("click", () =>
@@ -307,12 +536,73 @@ $("#selectAllButton").addEventListener("click", () => {
     .forEach((checkbox) => (checkbox.checked = true));
 });
 
+$("#deletePlaylistButton").addEventListener("click", async () => {
+  const playlistId = selectedPlaylistId();
+  if (!playlists.has(playlistId)) {
+    return;
+  }
+
+  if (await deletePlaylist(playlistId)) {
+    selectVideosFromPlaylist("");
+    playlistUtilVisible(false);
+  }
+});
+
+$("#exportPlaylistButton").addEventListener("click", () => {
+  const playlistId = selectedPlaylistId();
+  if (playlists.has(playlistId)) {
+    window.location = `/playlists/export?playlist_id=${encodeURIComponent(playlistId)}&format=m3u8`;
+  }
+});
+
+$("#importPlaylistButton").addEventListener("click", () =>
+  $("#importPlaylistInput").click(),
+);
+
+// named after the file, anything that isn't in the library gets listed
+$("#importPlaylistInput").addEventListener("change", async (e) => {
+  const file = e.target.files[0];
+  e.target.value = "";
+  if (!file) {
+    return;
+  }
+
+  const name = file.name.replace(/\.[^.]+$/, "");
+  // the raw file so the server can tell a latin-1 .m3u apart from utf-8
+  const ext = file.name.split(".").pop().toLowerCase();
+  const format = ["m3u", "m3u8", "xspf"].includes(ext) ? `&format=${ext}` : "";
+  const response = await fetch(
+    `/playlists/import?playlist_name=${encodeURIComponent(name)}&conflict=rename${format}`,
+    { method: "POST", body: file },
+  );
+
+  if (!response.ok) {
+    notyf.error(`Failed to import playlist: ${await response.text()}`);
+    return;
+  }
+
+  const { playlist_name, imported, unmatched } = await response.json();
+  notyf.success(`Imported ${imported} videos into ${playlist_name}`);
+  if (unmatched.length) {
+    notyf.error(`${unmatched.length} entries weren't in the library`);
+    console.warn("unmatched playlist entries", unmatched);
+  }
+
+  await fetchPlaylists();
+});
+
 let listeningForPaste = false;
+// "play" or "save"
+let pasteMode = "play";
 
-$("#playMediaButton").addEventListener("click", () => {
+function listenForPaste(mode) {
+  pasteMode = mode;
   listeningForPaste = true;
   $("#pasteBackdrop").classList.remove("hidden");
-});
+}
+
+$("#playMediaButton").addEventListener("click", () => listenForPaste("play"));
+$("#saveMediaButton").addEventListener("click", () => listenForPaste("save"));
 
 document.addEventListener("paste", (e) => {
   if (!listeningForPaste) {
@@ -324,8 +614,13 @@ document.addEventListener("paste", (e) => {
     return;
   }
 
+  listeningForPaste = false;
   $("#pasteBackdrop").classList.add("hidden");
-  playMedia(url);
+  if (pasteMode === "save") {
+    saveMedia(url);
+  } else {
+    playMedia(url);
+  }
 });
 
 document.addEventListener("keydown", async (e) => {
@@ -335,8 +630,8 @@ document.addEventListener("keydown", async (e) => {
   }
 });
 
-function selectVideosFromPlaylist(playlistName) {
-  const playlist = playlists.get(playlistName) || [];
+function selectVideosFromPlaylist(playlistId) {
+  const playlist = playlists.get(playlistId)?.videos ?? [];
 
   document.querySelectorAll(".video-item").forEach((item) => {
     const checkbox = item.querySelector(".video-select");
@@ -348,5 +643,31 @@ $("#playPauseButton").addEventListener("click", () => pressMediaKey(0));
 $("#nextButton").addEventListener("click", () => pressMediaKey(1));
 $("#backButton").addEventListener("click", () => pressMediaKey(2));
 
+// thumbnails are generated after the upload returns, refresh once they exist
+const events = new EventSource("/events");
+events.addEventListener("message", ({ data }) => {
+  const event = JSON.parse(data);
+  if (event.type === "download_failed") {
+    notyf.error(`Download failed: ${event.error}`);
+  }
+
+  // files copied straight into uploads/
+  if (event.type === "library") {
+    fetchVideos();
+    fetchPlaylists();
+    return;
+  }
+
+  if (event.type !== "processing") {
+    return;
+  }
+
+  if (event.stage === "done") {
+    fetchVideos();
+  } else if (event.stage === "failed") {
+    notyf.error(`Processing ${event.video} failed: ${event.error}`);
+  }
+});
+
 fetchVideos();
 fetchPlaylists();
@@ -0,0 +1,165 @@
+use std::path::PathBuf;
+
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+use simplelog::{info, warn};
+use tokio::fs;
+
+use crate::duplicates::DuplicatePolicy;
+
+const CONFIG_FILE: &str = "config.json";
+
+static CONFIG: OnceCell<Config> = OnceCell::new();
+
+// everything is optional in the file, missing keys fall back to the defaults below
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct Config {
+    // how many background jobs (thumbnails, downloads...) run at once
+    pub job_concurrency: usize,
+    // extra attempts before a job is marked as failed
+    pub job_retries: u32,
+    // finished jobs kept around for /jobs
+    pub job_history: usize,
+    pub transcode: TranscodeConfig,
+    // lufs every video gets nudged towards on playback, -16 is about what youtube uses
+    pub loudness_target: f64,
+    // picks up files copied straight into uploads/
+    pub watch_library: bool,
+    // how far into the video (0-1) to look for a thumbnail, skips most intros
+    pub thumbnail_position: f64,
+    // seconds before giving up on a thumbnail, some files make ffmpeg crawl
+    pub thumbnail_timeout: u64,
+    // checks the library for orphans & broken references on startup, see /maintenance
+    pub sweep_on_startup: bool,
+    // also fixes what it finds instead of just logging it
+    pub sweep_fix_on_startup: bool,
+    // deleted videos are purged from the trash after this many days, 0 keeps them forever
+    pub trash_days: u64,
+    pub storage: StorageConfig,
+    // what happens to an upload that's byte for byte the same as an existing video
+    pub duplicate_policy: DuplicatePolicy,
+    // folders outside of uploads/ that are part of the library too
+    pub roots: Vec<LibraryRoot>,
+}
+
+// e.x. a nas mount that shouldn't be copied into uploads/
+#[derive(Serialize, Deserialize, Debug)]
+pub struct LibraryRoot {
+    // videos from it are named 'name:folder/video.mp4'
+    pub name: String,
+    pub path: PathBuf,
+    // refuses deleting, renaming & anything else that would write to it
+    #[serde(default)]
+    pub read_only: bool,
+}
+
+// a full sd card corrupts crt.log & stops vlc from playing anything, see /storage
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct StorageConfig {
+    // megabytes everything the server writes can take up, 0 for no limit
+    pub quota_mb: u64,
+    // uploads are refused if they'd leave less than this free on the disk
+    pub min_free_mb: u64,
+    // deletes the least recently played videos to stay under the quota instead of refusing uploads
+    pub retention: bool,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self {
+            quota_mb: 0,
+            min_free_mb: 512,
+            retention: false,
+        }
+    }
+}
+
+// what new videos get converted into so the pi doesn't have to decode 4k hevc for a 480 line tv
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct TranscodeConfig {
+    pub enabled: bool,
+    // 640x480 for ntsc, 720x576 for pal
+    pub width: u32,
+    pub height: u32,
+    // x264 crf, lower is better looking & bigger
+    pub quality: u8,
+    // false swaps the original out for the transcoded version
+    pub keep_original: bool,
+}
+
+impl Default for TranscodeConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            width: 640,
+            height: 480,
+            quality: 23,
+            keep_original: true,
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            job_concurrency: 2,
+            job_retries: 2,
+            job_history: 200,
+            transcode: TranscodeConfig::default(),
+            loudness_target: -16.0,
+            watch_library: true,
+            thumbnail_position: 0.2,
+            thumbnail_timeout: 60,
+            sweep_on_startup: true,
+            sweep_fix_on_startup: false,
+            trash_days: 30,
+            storage: StorageConfig::default(),
+            duplicate_policy: DuplicatePolicy::default(),
+            roots: vec![],
+        }
+    }
+}
+
+// same deal as flags.txt, written out with the defaults if it doesn't exist
+pub async fn load() {
+    let mut config: Config = if let Ok(data) = fs::read(CONFIG_FILE).await {
+        match serde_json::from_slice(&data) {
+            Ok(config) => config,
+            Err(e) => {
+                warn!("failed to parse {CONFIG_FILE}, using defaults: {e}");
+                Config::default()
+            }
+        }
+    } else {
+        let config = Config::default();
+        if let Ok(data) = serde_json::to_vec_pretty(&config) {
+            let _ = fs::write(CONFIG_FILE, data).await;
+        }
+
+        config
+    };
+
+    // names end up in video names & folder names so they can't have anything weird in them
+    config.roots.retain(|root| {
+        let valid = !root.name.is_empty()
+            && root
+                .name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !valid {
+            warn!("ignoring library root with invalid name '{}'", root.name);
+        }
+
+        valid
+    });
+
+    info!("loaded config: {config:?}");
+    let _ = CONFIG.set(config);
+}
+
+pub fn config() -> &'static Config {
+    CONFIG.get_or_init(Config::default)
+}
@@ -0,0 +1,282 @@
+use std::{
+    path::{Path, PathBuf},
+    process::Stdio,
+};
+
+use anyhow::{bail, Context, Result};
+use axum::http::StatusCode;
+use serde::{Deserialize, Serialize};
+use simplelog::warn;
+use tokio::{
+    fs,
+    io::{AsyncBufReadExt, BufReader},
+    process::Command,
+    task,
+};
+
+use crate::{
+    events::{self, Event},
+    jobs::JobContext,
+    playlist, processing,
+    safe_path::SafePath,
+    storage,
+    uploads::{self, ConflictPolicy},
+    web_util::StatusError,
+    DOWNLOAD_PATH,
+};
+
+// yt-dlp prints this before every progress line so we can tell them apart from the file path
+const PROGRESS_PREFIX: &str = "progress:";
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Download {
+    pub url: String,
+    // saved under the media title if none
+    pub name: Option<String>,
+    pub folder: Option<SafePath>,
+    // raw yt-dlp format, wins over max_height
+    pub format: Option<String>,
+    pub max_height: Option<u32>,
+    // playlist name, made if there isn't one with that name yet
+    pub playlist: Option<String>,
+    pub conflict: ConflictPolicy,
+}
+
+// yt-dlp can do more than http but nothing else makes sense coming from the ui
+pub fn validate_url(url: &str) -> Result<(), StatusError> {
+    if url.starts_with("https://") || url.starts_with("http://") {
+        return Ok(());
+    }
+
+    Err(StatusError::new(
+        StatusCode::BAD_REQUEST,
+        "only http(s) urls are supported",
+    ))
+}
+
+// tmp/downloads/<job id>, removed on drop so a cancelled job that never gets to the end still cleans up
+struct DownloadDir(PathBuf);
+
+impl Drop for DownloadDir {
+    fn drop(&mut self) {
+        // blocking but it's whatever yt-dlp left half done
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+// runs as a job, progress & the result go out over /events
+pub async fn run(download: &Download, ctx: &JobContext) -> Result<()> {
+    let dir = DownloadDir(Path::new(DOWNLOAD_PATH).join(&ctx.id));
+
+    let result = save_to_library(download, ctx, &dir.0).await;
+    drop(dir);
+
+    match result {
+        Ok(path) => {
+            ctx.log(format!("saved to '{}'", path.display())).await;
+            events::emit(Event::Downloaded {
+                id: ctx.id.clone(),
+                url: download.url.clone(),
+                path: path.to_string_lossy().into_owned(),
+            });
+
+            processing::process(&path).await;
+            Ok(())
+        }
+        Err(e) => {
+            if ctx.is_last_attempt() {
+                events::emit(Event::DownloadFailed {
+                    id: ctx.id.clone(),
+                    url: download.url.clone(),
+                    error: e.to_string(),
+                });
+            }
+
+            Err(e)
+        }
+    }
+}
+
+async fn save_to_library(download: &Download, ctx: &JobContext, dir: &Path) -> Result<PathBuf> {
+    // the size isn't known up front, this at least doesn't start on a full disk
+    let room = storage::make_room(0).await?;
+    fs::create_dir_all(dir).await?;
+
+    let downloaded = yt_dlp(download, ctx, dir, room).await?;
+    let name = library_name(download, &ctx.id, &downloaded)?;
+    // same check as uploads, yt-dlp will happily save an image or a subtitle file
+    uploads::validate(&downloaded, &name).await?;
+
+    let path = uploads::move_into_library(&downloaded, &name, download.conflict).await?;
+
+    if let Some(playlist) = &download.playlist {
+        playlist::add_video(playlist, &path).await?;
+    }
+
+    Ok(path)
+}
+
+// returns where yt-dlp put the file
+async fn yt_dlp(download: &Download, ctx: &JobContext, dir: &Path, room: u64) -> Result<PathBuf> {
+    let format = download.format.clone().unwrap_or_else(|| {
+        download.max_height.map_or_else(
+            || "bv*+ba/b".to_string(),
+            |h| format!("bv*[height<={h}]+ba/b[height<={h}]/b"),
+        )
+    });
+
+    ctx.log(format!("downloading with format '{format}'")).await;
+
+    // yt-dlp --quiet --progress --newline -f "bv*+ba/b" --windows-filenames -o "tmp/downloads/id/%(title)s.%(ext)s" url
+    let mut child = Command::new("yt-dlp")
+        .arg("--quiet")
+        .arg("--no-warnings")
+        .arg("--no-playlist")
+        .arg("--progress")
+        .arg("--newline")
+        .arg("--progress-template")
+        .arg(format!(
+            "download:{PROGRESS_PREFIX}%(progress.downloaded_bytes)s/%(progress.total_bytes,progress.total_bytes_estimate)s"
+        ))
+        .arg("-f")
+        .arg(format)
+        .arg("--merge-output-format")
+        .arg("mp4")
+        // gets rid of the same characters SafePath rejects
+        .arg("--windows-filenames")
+        // per stream, merging needs the streams & the merged file at once so only half
+        .arg("--max-filesize")
+        .arg((room / 2).to_string())
+        .arg("-o")
+        .arg(dir.join("%(title)s.%(ext)s"))
+        .arg("--print")
+        .arg("after_move:filepath")
+        // a url starting with '-' would be read as an option otherwise
+        .arg("--")
+        .arg(&download.url)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .context("failed to start yt-dlp")?;
+
+    let stdout = child.stdout.take().context("no yt-dlp stdout")?;
+    let stderr = child.stderr.take().context("no yt-dlp stderr")?;
+
+    // with --quiet the progress lines go to stderr along with any errors
+    let (url, id) = (download.url.clone(), ctx.id.clone());
+    let stderr_task = task::spawn(async move {
+        let mut lines = BufReader::new(stderr).lines();
+        let mut collected = vec![];
+        while let Ok(Some(line)) = lines.next_line().await {
+            match progress(&line) {
+                Some((downloaded, total)) => events::emit(Event::DownloadProgress {
+                    id: id.clone(),
+                    url: url.clone(),
+                    downloaded,
+                    total,
+                }),
+                None => collected.push(line),
+            }
+        }
+
+        collected.join("\n")
+    });
+
+    // just the file path from --print
+    let mut file_path = None;
+    let mut lines = BufReader::new(stdout).lines();
+    while let Some(line) = lines.next_line().await? {
+        if !line.trim().is_empty() {
+            file_path = Some(PathBuf::from(line.trim()));
+        }
+    }
+
+    let status = child.wait().await?;
+    let stderr = stderr_task.await.unwrap_or_default();
+    if !status.success() {
+        bail!("yt-dlp failed: {}", stderr.trim());
+    }
+
+    // yt-dlp skips anything over --max-filesize without failing
+    let file_path = file_path.with_context(|| {
+        format!(
+            "yt-dlp didn't save anything, it might be bigger than the {} mb left",
+            room / 2 / (1024 * 1024)
+        )
+    })?;
+    if !file_path.is_file() {
+        bail!("yt-dlp output '{}' doesn't exist", file_path.display());
+    }
+
+    Ok(file_path)
+}
+
+// 'progress:1234/5678' -> (1234, Some(5678)), total can be 'NA'
+fn progress(line: &str) -> Option<(u64, Option<u64>)> {
+    let (downloaded, total) = line.trim().strip_prefix(PROGRESS_PREFIX)?.split_once('/')?;
+    let downloaded = downloaded.trim().parse::<f64>().ok()?;
+
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    let as_bytes = |n: f64| n as u64;
+
+    Some((
+        as_bytes(downloaded),
+        total.trim().parse::<f64>().ok().map(as_bytes),
+    ))
+}
+
+fn library_name(download: &Download, id: &str, downloaded: &Path) -> Result<SafePath> {
+    let ext = downloaded
+        .extension()
+        .map(|e| format!(".{}", e.to_string_lossy()))
+        .unwrap_or_default();
+
+    let file_name = match &download.name {
+        // let people leave off the extension
+        Some(name) if Path::new(name).extension().is_some() => name.clone(),
+        Some(name) => format!("{name}{ext}"),
+        None => downloaded
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned(),
+    };
+
+    let name = match &download.folder {
+        Some(folder) => format!("{folder}/{file_name}"),
+        None => file_name,
+    };
+
+    if let Ok(name) = SafePath::new(&name) {
+        return Ok(name);
+    }
+
+    // titles can still be weird, e.x. starting with a dot
+    warn!("'{name}' isn't a valid name, falling back to the download id");
+    let fallback = format!("{id}{ext}");
+    Ok(match &download.folder {
+        Some(folder) => SafePath::new(&format!("{folder}/{fallback}"))?,
+        None => SafePath::new(&fallback)?,
+    })
+}
+
+// anything in here at startup never finished
+pub async fn clean_temp() {
+    let _ = fs::remove_dir_all(DOWNLOAD_PATH).await;
+    let _ = fs::create_dir_all(DOWNLOAD_PATH).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn progress_lines() {
+        assert_eq!(progress("progress:1024/4096"), Some((1024, Some(4096))));
+        assert_eq!(progress("progress:1024.0/NA"), Some((1024, None)));
+        assert_eq!(progress("progress:NA/NA"), None);
+        assert_eq!(progress("ERROR: [youtube] abc: Video unavailable"), None);
+        assert_eq!(progress("uploads/progress:1/2.mp4"), None);
+    }
+}
@@ -0,0 +1,167 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use axum::{http::StatusCode, routing::get, Json, Router};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use simplelog::{info, warn};
+use tokio::{fs::File, io::AsyncReadExt};
+
+use crate::{
+    config::config,
+    metadata,
+    safe_path::SafePath,
+    uploads::{self, ConflictPolicy, TempFile},
+    video_name, video_path,
+    web_util::StatusError,
+    AppState,
+};
+
+// what to do when an upload has the exact same content as something already in the library
+#[derive(Serialize, Deserialize, Default, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DuplicatePolicy {
+    // saved anyway, still shows up in /duplicates
+    #[default]
+    Keep,
+    Reject,
+    // hard linked to the existing file so it doesn't take up any more space
+    Link,
+}
+
+pub fn hex(digest: &[u8]) -> String {
+    digest.iter().fold(String::new(), |mut hex, byte| {
+        let _ = write!(hex, "{byte:02x}");
+        hex
+    })
+}
+
+// for things that weren't streamed through stream_to_file, e.x. resumable uploads & old videos
+pub async fn hash_file(path: &Path) -> std::io::Result<String> {
+    let mut file = File::open(path).await?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0; 1024 * 1024];
+
+    loop {
+        let read = file.read(&mut buf).await?;
+        if read == 0 {
+            break;
+        }
+
+        hasher.update(&buf[..read]);
+    }
+
+    Ok(hex(&hasher.finalize()))
+}
+
+// a video still in the library with the same content
+async fn find(sha256: &str) -> Option<PathBuf> {
+    for video in metadata::videos().await {
+        if metadata::get(&video).await.sha256.as_deref() == Some(sha256) && video.is_file() {
+            return Some(video);
+        }
+    }
+
+    None
+}
+
+// called once an upload is complete but before it's moved into the library
+// returns where it ended up if it got linked, the upload itself can be thrown away then
+pub async fn check(
+    sha256: &str,
+    name: &SafePath,
+    conflict: ConflictPolicy,
+) -> Result<Option<PathBuf>> {
+    let Some(existing) = find(sha256).await else {
+        return Ok(None);
+    };
+
+    let existing_name = video_name(&existing).unwrap_or_else(|| existing.display().to_string());
+    match config().duplicate_policy {
+        DuplicatePolicy::Keep => {
+            info!("'{name}' is the same as '{existing_name}', keeping it anyway");
+            Ok(None)
+        }
+        DuplicatePolicy::Reject => Err(StatusError::new(
+            StatusCode::CONFLICT,
+            format!("'{name}' is the same as '{existing_name}'"),
+        )
+        .into()),
+        DuplicatePolicy::Link => link(&existing, sha256, name, conflict).await,
+    }
+}
+
+async fn link(
+    existing: &Path,
+    sha256: &str,
+    name: &SafePath,
+    conflict: ConflictPolicy,
+) -> Result<Option<PathBuf>> {
+    // overwriting a video with itself
+    let name = uploads::resolve_conflict(name, conflict)?;
+    if video_path(&name) == existing {
+        return Ok(Some(existing.to_path_buf()));
+    }
+
+    // linked into tmp/ first so it goes through the same conflict handling as a normal upload
+    let temp = TempFile::new();
+    if let Err(e) = tokio::fs::hard_link(existing, temp.path()).await {
+        // e.x. the existing one is on a nas
+        warn!(
+            "can't link to '{}', keeping a copy: {e}",
+            existing.display()
+        );
+        return Ok(None);
+    }
+
+    let path = temp.persist(&name, conflict).await?;
+    record(&path, sha256.to_string()).await;
+    info!("linked '{}' to '{}'", path.display(), existing.display());
+
+    Ok(Some(path))
+}
+
+pub async fn record(video: &Path, sha256: String) {
+    if let Err(e) = metadata::update(video, |meta| meta.sha256 = Some(sha256)).await {
+        warn!("failed to save hash for '{}': {e:?}", video.display());
+    }
+}
+
+pub fn router() -> Router<AppState> {
+    Router::new().route("/duplicates", get(duplicates))
+}
+
+#[derive(Serialize)]
+struct DuplicateGroup {
+    sha256: String,
+    videos: Vec<String>,
+}
+
+// only knows about videos that have been hashed, processing catches up on the rest
+async fn duplicates() -> Json<Vec<DuplicateGroup>> {
+    let mut groups = BTreeMap::<String, Vec<String>>::new();
+    for video in metadata::videos().await {
+        let Some(sha256) = metadata::get(&video).await.sha256 else {
+            continue;
+        };
+
+        if let Some(name) = video_name(&video).filter(|_| video.is_file()) {
+            groups.entry(sha256).or_default().push(name);
+        }
+    }
+
+    Json(
+        groups
+            .into_iter()
+            .filter(|(_, videos)| videos.len() > 1)
+            .map(|(sha256, mut videos)| {
+                videos.sort();
+                DuplicateGroup { sha256, videos }
+            })
+            .collect(),
+    )
+}
@@ -0,0 +1,185 @@
+use std::{
+    convert::Infallible,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+};
+
+use axum::response::sse::{self, KeepAlive, Sse};
+use futures_util::Stream;
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use tokio::sync::broadcast;
+use tokio_stream::{wrappers::BroadcastStream, StreamExt};
+
+use crate::jobs::JobState;
+
+// anything slower than this just misses events, they're only for the ui
+const CHANNEL_SIZE: usize = 256;
+
+// don't spam the channel with every single chunk
+const MIN_PROGRESS_STEP: u64 = 1024 * 1024;
+const UNKNOWN_TOTAL_STEP: u64 = 8 * 1024 * 1024;
+
+static EVENTS: Lazy<broadcast::Sender<Event>> = Lazy::new(|| broadcast::channel(CHANNEL_SIZE).0);
+
+#[derive(Serialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    UploadProgress {
+        name: String,
+        received: u64,
+        total: Option<u64>,
+    },
+    Uploaded {
+        name: String,
+        path: String,
+    },
+    UploadFailed {
+        name: String,
+        error: String,
+    },
+    Processing {
+        video: String,
+        stage: Stage,
+        error: Option<String>,
+    },
+    DownloadProgress {
+        id: String,
+        url: String,
+        downloaded: u64,
+        total: Option<u64>,
+    },
+    Downloaded {
+        id: String,
+        url: String,
+        path: String,
+    },
+    DownloadFailed {
+        id: String,
+        url: String,
+        error: String,
+    },
+    Job {
+        id: String,
+        task: String,
+        state: JobState,
+    },
+    // something in the library changed outside of a request, e.x. rsync or storage retention
+    Library {
+        video: String,
+        change: LibraryChange,
+    },
+}
+
+#[derive(Serialize, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum LibraryChange {
+    Added,
+    Removed,
+}
+
+#[derive(Serialize, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Stage {
+    Probe,
+    Thumbnail,
+    Preview,
+    Transcode,
+    Loudness,
+    Done,
+    Failed,
+}
+
+pub fn emit(event: Event) {
+    // only errors if nobody is listening
+    let _ = EVENTS.send(event);
+}
+
+// GET /events
+pub async fn event_stream() -> Sse<impl Stream<Item = Result<sse::Event, Infallible>>> {
+    let stream = BroadcastStream::new(EVENTS.subscribe()).filter_map(|event| {
+        // lagged receivers just skip ahead
+        let event = event.ok()?;
+        let data = serde_json::to_string(&event).ok()?;
+
+        Some(Ok(sse::Event::default().data(data)))
+    });
+
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+// tracks one upload, if it gets dropped before being settled the upload died halfway
+pub struct UploadProgress {
+    name: String,
+    total: Option<u64>,
+    received: AtomicU64,
+    last_emitted: AtomicU64,
+    settled: AtomicBool,
+}
+
+impl UploadProgress {
+    pub fn new(name: &str, total: Option<u64>) -> Self {
+        Self::starting_at(name, total, 0)
+    }
+
+    // for resumable uploads that already have some of the file
+    pub fn starting_at(name: &str, total: Option<u64>, received: u64) -> Self {
+        Self {
+            name: name.to_string(),
+            total,
+            received: AtomicU64::new(received),
+            last_emitted: AtomicU64::new(received),
+            settled: AtomicBool::new(false),
+        }
+    }
+
+    pub fn advance(&self, bytes: usize) {
+        let received = self.received.fetch_add(bytes as u64, Ordering::Relaxed) + bytes as u64;
+
+        let step = self.total.map_or(UNKNOWN_TOTAL_STEP, |total| {
+            (total / 100).max(MIN_PROGRESS_STEP)
+        });
+
+        if received - self.last_emitted.load(Ordering::Relaxed) < step {
+            return;
+        }
+
+        self.last_emitted.store(received, Ordering::Relaxed);
+        emit(Event::UploadProgress {
+            name: self.name.clone(),
+            received,
+            total: self.total,
+        });
+    }
+
+    pub fn finish(&self, path: &str) {
+        self.settled.store(true, Ordering::Relaxed);
+        emit(Event::Uploaded {
+            name: self.name.clone(),
+            path: path.to_string(),
+        });
+    }
+
+    pub fn fail(&self, error: &anyhow::Error) {
+        self.settled.store(true, Ordering::Relaxed);
+        emit(Event::UploadFailed {
+            name: self.name.clone(),
+            error: error.to_string(),
+        });
+    }
+
+    // went fine but isn't finished, e.x. one chunk of a resumable upload
+    pub fn settle(&self) {
+        self.settled.store(true, Ordering::Relaxed);
+    }
+}
+
+impl Drop for UploadProgress {
+    fn drop(&mut self) {
+        if !self.settled.load(Ordering::Relaxed) {
+            emit(Event::UploadFailed {
+                name: self.name.clone(),
+                error: "upload was interrupted".to_string(),
+            });
+        }
+    }
+}
@@ -0,0 +1,466 @@
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{Arc, Mutex as StdMutex, PoisonError},
+};
+
+use anyhow::{Context, Result};
+use axum::{
+    extract::{self, Query},
+    http::StatusCode,
+    routing::{get, post},
+    Json, Router,
+};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use simplelog::{info, warn};
+use tokio::{
+    fs,
+    sync::{Mutex, MutexGuard, Notify, Semaphore},
+    task,
+};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    config::config,
+    downloads::{self, Download},
+    events::{self, Event},
+    maintenance, now, processing, thumbnails,
+    uploads::unique_id,
+    web_util::{StatusError, WebResult},
+    AppState,
+};
+
+const JOBS_FILE: &str = "jobs.json";
+// a chatty job shouldn't be able to blow up jobs.json
+const MAX_LOG_LINES: usize = 200;
+
+// every long running media task goes through here instead of a random task::spawn
+// jobs are persisted to jobs.json so a restart picks up where it left off
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum JobTask {
+    // probe + thumbnail for something new in the library
+    Process { video: PathBuf },
+    Download(Download),
+    Thumbnail { video: PathBuf },
+    DecodeCheck { video: PathBuf },
+}
+
+impl JobTask {
+    // a second one of these while one is running would fight over the same files
+    // thumbnails & decode checks are cheap & might be asked for again because something changed
+    const fn dedupes_running(&self) -> bool {
+        matches!(self, Self::Process { .. } | Self::Download(_))
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            Self::Process { video } => format!("process '{}'", video.display()),
+            Self::Download(download) => format!("download '{}'", download.url),
+            Self::Thumbnail { video } => format!("regenerate thumbnail for '{}'", video.display()),
+            Self::DecodeCheck { video } => format!("decode check '{}'", video.display()),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum JobState {
+    Queued,
+    Running,
+    Done,
+    Failed,
+    Cancelled,
+}
+
+impl JobState {
+    const fn finished(self) -> bool {
+        matches!(self, Self::Done | Self::Failed | Self::Cancelled)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Job {
+    pub id: String,
+    pub task: JobTask,
+    pub state: JobState,
+    pub attempts: u32,
+    pub error: Option<String>,
+    pub log: Vec<String>,
+    // unix seconds
+    pub created_at: u64,
+    pub updated_at: u64,
+}
+
+// handed to whatever is running the job
+pub struct JobContext {
+    pub id: String,
+    attempt: u32,
+}
+
+impl JobContext {
+    pub async fn log(&self, line: impl Into<String>) {
+        let line = line.into();
+        info!("[job {}] {line}", self.id);
+
+        // not saved on its own, a chatty job would rewrite jobs.json for every line
+        // it goes out with the next state change instead
+        let mut jobs = JOBS.lock().await;
+        if let Some(job) = jobs.iter_mut().find(|j| j.id == self.id) {
+            job.log.push(line);
+            if job.log.len() > MAX_LOG_LINES {
+                job.log.remove(0);
+            }
+        }
+    }
+
+    // so failure events only go out once the job is actually giving up
+    pub fn is_last_attempt(&self) -> bool {
+        self.attempt > config().job_retries
+    }
+}
+
+// tokio mutex so saves happen in order, same as metadata
+static JOBS: Lazy<Mutex<Vec<Job>>> = Lazy::new(Mutex::default);
+static WAKE: Lazy<Notify> = Lazy::new(Notify::new);
+static RUNNING: Lazy<StdMutex<HashMap<String, CancellationToken>>> = Lazy::new(StdMutex::default);
+
+pub async fn load() -> Result<()> {
+    let Ok(data) = fs::read(JOBS_FILE).await else {
+        return Ok(());
+    };
+
+    let mut loaded = match serde_json::from_slice::<Vec<Job>>(&data) {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            // same as metadata, keep it around instead of overwriting it on the next save
+            let _ = fs::rename(JOBS_FILE, format!("{JOBS_FILE}.broken")).await;
+            return Err(e).with_context(|| format!("failed to parse {JOBS_FILE}"));
+        }
+    };
+
+    for job in loaded.iter_mut().filter(|j| j.state == JobState::Running) {
+        job.state = JobState::Queued;
+        job.log
+            .push("interrupted by a restart, requeued".to_string());
+    }
+
+    let queued = loaded
+        .iter()
+        .filter(|j| j.state == JobState::Queued)
+        .count();
+    info!("loaded {} jobs, {queued} queued", loaded.len());
+
+    *JOBS.lock().await = loaded;
+
+    Ok(())
+}
+
+pub fn start() {
+    drop(task::spawn(runner()));
+}
+
+async fn runner() {
+    let semaphore = Arc::new(Semaphore::new(config().job_concurrency.max(1)));
+
+    loop {
+        let Ok(permit) = Arc::clone(&semaphore).acquire_owned().await else {
+            return;
+        };
+
+        let Some(job) = take_next().await else {
+            drop(permit);
+            WAKE.notified().await;
+            continue;
+        };
+
+        drop(task::spawn(async move {
+            run(job).await;
+            drop(permit);
+        }));
+    }
+}
+
+// returns the id, or the id of an identical job that hasn't started yet
+// running ones only count for tasks where doing it twice at once would break things
+pub async fn enqueue(task: JobTask) -> String {
+    let id = update(|jobs| push(jobs, task)).await;
+
+    WAKE.notify_one();
+    id
+}
+
+// same as enqueue but jobs.json only gets written once, for queueing up the whole library
+pub async fn enqueue_all(tasks: impl IntoIterator<Item = JobTask>) -> Vec<String> {
+    let ids = update(|jobs| tasks.into_iter().map(|task| push(jobs, task)).collect()).await;
+
+    WAKE.notify_one();
+    ids
+}
+
+fn push(jobs: &mut Vec<Job>, task: JobTask) -> String {
+    if let Some(existing) = jobs.iter().find(|j| {
+        j.task == task
+            && (j.state == JobState::Queued
+                || (j.state == JobState::Running && task.dedupes_running()))
+    }) {
+        return existing.id.clone();
+    }
+
+    let now = now();
+    let job = Job {
+        id: unique_id(),
+        task,
+        state: JobState::Queued,
+        attempts: 0,
+        error: None,
+        log: vec![],
+        created_at: now,
+        updated_at: now,
+    };
+
+    info!("queued job {} to {}", job.id, job.task.describe());
+    emit_state(&job);
+    let id = job.id.clone();
+    jobs.push(job);
+
+    id
+}
+
+async fn take_next() -> Option<Job> {
+    update(|jobs| {
+        let job = jobs.iter_mut().find(|j| j.state == JobState::Queued)?;
+
+        job.state = JobState::Running;
+        job.attempts += 1;
+        job.updated_at = now();
+        emit_state(job);
+
+        Some(job.clone())
+    })
+    .await
+}
+
+async fn run(job: Job) {
+    let token = CancellationToken::new();
+    RUNNING
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .insert(job.id.clone(), token.clone());
+
+    let ctx = JobContext {
+        id: job.id.clone(),
+        attempt: job.attempts,
+    };
+    ctx.log(format!(
+        "attempt {} to {}",
+        job.attempts,
+        job.task.describe()
+    ))
+    .await;
+
+    // dropping the future on cancel kills any child process since they're all kill_on_drop
+    let result = tokio::select! {
+        result = execute(&job.task, &ctx) => Some(result),
+        () = token.cancelled() => None,
+    };
+
+    RUNNING
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .remove(&job.id);
+
+    let (state, error) = match result {
+        None => (JobState::Cancelled, None),
+        Some(Ok(())) => (JobState::Done, None),
+        Some(Err(e)) if !ctx.is_last_attempt() => {
+            ctx.log(format!("failed, retrying: {e:#}")).await;
+            (JobState::Queued, Some(format!("{e:#}")))
+        }
+        Some(Err(e)) => {
+            ctx.log(format!("failed: {e:#}")).await;
+            (JobState::Failed, Some(format!("{e:#}")))
+        }
+    };
+
+    set_state(&job.id, state, error).await;
+    if state == JobState::Queued {
+        WAKE.notify_one();
+    }
+}
+
+async fn execute(task: &JobTask, ctx: &JobContext) -> Result<()> {
+    match task {
+        JobTask::Process { video } => processing::run(video, ctx).await,
+        JobTask::Download(download) => downloads::run(download, ctx).await,
+        JobTask::Thumbnail { video } => thumbnails::regenerate(video, ctx).await,
+        JobTask::DecodeCheck { video } => maintenance::decode_check(video, ctx).await,
+    }
+}
+
+async fn set_state(id: &str, state: JobState, error: Option<String>) {
+    update(|jobs| {
+        if let Some(job) = jobs.iter_mut().find(|j| j.id == id) {
+            job.state = state;
+            job.error = error;
+            job.updated_at = now();
+            emit_state(job);
+        }
+
+        prune(jobs);
+    })
+    .await;
+}
+
+// only keeps the newest finished jobs
+fn prune(jobs: &mut Vec<Job>) {
+    let finished = jobs.iter().filter(|j| j.state.finished()).count();
+    let mut to_remove = finished.saturating_sub(config().job_history);
+
+    // oldest first since jobs are pushed in order
+    jobs.retain(|j| {
+        if to_remove > 0 && j.state.finished() {
+            to_remove -= 1;
+            return false;
+        }
+
+        true
+    });
+}
+
+fn emit_state(job: &Job) {
+    events::emit(Event::Job {
+        id: job.id.clone(),
+        task: job.task.describe(),
+        state: job.state,
+    });
+}
+
+// every state change goes through here so jobs.json is always up to date, log lines ride along
+// a failed save only gets logged, the queue in memory is still right
+// the guard is moved into save, clippy just can't tell
+#[allow(clippy::significant_drop_tightening)]
+async fn update<R>(f: impl FnOnce(&mut Vec<Job>) -> R) -> R {
+    let mut jobs = JOBS.lock().await;
+    let result = f(&mut jobs);
+
+    if let Err(e) = save(jobs).await {
+        warn!("failed to save jobs: {e:?}");
+    }
+
+    result
+}
+
+// takes the guard so it's held until the file is written
+async fn save(jobs: MutexGuard<'_, Vec<Job>>) -> Result<()> {
+    // not pretty, this gets written a lot & usually onto an sd card
+    let data = serde_json::to_vec(&*jobs)?;
+
+    let tmp_path = format!("{JOBS_FILE}.tmp");
+    fs::write(&tmp_path, data).await?;
+    fs::rename(&tmp_path, JOBS_FILE).await?;
+
+    Ok(())
+}
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/jobs", get(list_jobs))
+        .route("/jobs/:id", get(get_job).delete(cancel_job))
+        .route("/jobs/:id/retry", post(retry_job))
+}
+
+#[derive(Deserialize)]
+struct JobsQuery {
+    state: Option<JobState>,
+}
+
+async fn list_jobs(Query(JobsQuery { state }): Query<JobsQuery>) -> Json<Vec<Job>> {
+    let jobs = JOBS
+        .lock()
+        .await
+        .iter()
+        .filter(|j| state.is_none_or(|s| j.state == s))
+        .cloned()
+        .collect();
+
+    Json(jobs)
+}
+
+async fn get_job(extract::Path(id): extract::Path<String>) -> WebResult<Json<Job>> {
+    JOBS.lock()
+        .await
+        .iter()
+        .find(|j| j.id == id)
+        .cloned()
+        .map(Json)
+        .ok_or_else(|| not_found().into())
+}
+
+async fn cancel_job(extract::Path(id): extract::Path<String>) -> WebResult {
+    update(|jobs| {
+        let job = jobs.iter_mut().find(|j| j.id == id).ok_or_else(not_found)?;
+
+        match job.state {
+            JobState::Queued => {
+                job.state = JobState::Cancelled;
+                job.updated_at = now();
+                emit_state(job);
+            }
+            JobState::Running => {
+                // run() sees this and marks it as cancelled itself
+                if let Some(token) = RUNNING
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .get(&id)
+                {
+                    token.cancel();
+                }
+            }
+            _ => {
+                return Err(StatusError::new(
+                    StatusCode::CONFLICT,
+                    "job already finished",
+                ))
+            }
+        }
+
+        info!("cancelling job {id}");
+        Ok(())
+    })
+    .await
+    .map_err(Into::into)
+}
+
+async fn retry_job(extract::Path(id): extract::Path<String>) -> WebResult {
+    update(|jobs| {
+        let job = jobs.iter_mut().find(|j| j.id == id).ok_or_else(not_found)?;
+
+        if !matches!(job.state, JobState::Failed | JobState::Cancelled) {
+            return Err(StatusError::new(
+                StatusCode::CONFLICT,
+                "only failed or cancelled jobs can be retried",
+            ));
+        }
+
+        job.state = JobState::Queued;
+        job.attempts = 0;
+        job.error = None;
+        job.updated_at = now();
+        job.log.push("retried manually".to_string());
+        emit_state(job);
+
+        info!("retrying job {id}");
+        Ok(())
+    })
+    .await?;
+
+    WAKE.notify_one();
+    Ok(())
+}
+
+fn not_found() -> StatusError {
+    StatusError::new(StatusCode::NOT_FOUND, "job not found")
+}
@@ -0,0 +1,287 @@
+use std::{
+    ffi::OsStr,
+    iter,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context};
+use axum::http::StatusCode;
+use once_cell::sync::Lazy;
+use simplelog::{info, warn};
+use tokio::fs;
+
+use crate::{
+    config::{config, LibraryRoot},
+    metadata, playlist,
+    previews::preview_path,
+    safe_path::{ensure_inside, SafePath},
+    thumbnails::thumbnail_path,
+    transcode::optimized_path,
+    video_path,
+    web_util::StatusError,
+    OPTIMIZED_PATH, PREVIEW_PATH, THUMB_PATH, VIDEO_PATH,
+};
+
+// folders that mirror the library with something generated per video
+pub const MIRRORS: [&str; 3] = [THUMB_PATH, OPTIMIZED_PATH, PREVIEW_PATH];
+
+// what counts as a video, anything else in a root (e.x. .nfo, .srt or cover.jpg on a nas) is left alone
+const MEDIA_EXTENSIONS: [&str; 30] = [
+    "mp4", "m4v", "mkv", "webm", "mov", "avi", "wmv", "flv", "mpg", "mpeg", "ts", "m2ts", "mts",
+    "vob", "ogv", "3gp", "asf", "divx", "rmvb", "mxf", "mp3", "m4a", "aac", "flac", "wav", "ogg",
+    "oga", "opus", "wma", "mka",
+];
+
+// uploads/ is a root without a name so its videos keep plain names
+static UPLOADS: Lazy<LibraryRoot> = Lazy::new(|| LibraryRoot {
+    name: String::new(),
+    path: PathBuf::from(VIDEO_PATH),
+    read_only: false,
+});
+
+// uploads/ first, then whatever is in the config
+pub fn roots() -> impl Iterator<Item = &'static LibraryRoot> {
+    iter::once(&*UPLOADS).chain(config().roots.iter())
+}
+
+pub fn root(name: &str) -> Option<&'static LibraryRoot> {
+    config().roots.iter().find(|root| root.name == name)
+}
+
+// which root a video is in & where it is inside of it
+pub fn split_root(video: &Path) -> Option<(&'static LibraryRoot, &Path)> {
+    // configured ones first in case one of them is inside of uploads/
+    config()
+        .roots
+        .iter()
+        .chain(iter::once(&*UPLOADS))
+        .find_map(|root| Some((root, video.strip_prefix(&root.path).ok()?)))
+}
+
+// where generated files for a video go inside of a mirror folder
+// nas:folder/a.mp4 -> nas:/folder/a.mp4, can't collide with uploads/ since names can't have a colon
+#[must_use]
+pub fn mirror_relative(video: &Path) -> PathBuf {
+    match split_root(video) {
+        Some((root, relative)) if root.name.is_empty() => relative.to_path_buf(),
+        Some((root, relative)) => Path::new(&format!("{}:", root.name)).join(relative),
+        None => PathBuf::from(video.file_name().unwrap_or_default()),
+    }
+}
+
+// video_path for any root, 'nas:folder/a.mp4' -> /mnt/nas/folder/a.mp4
+pub fn resolve(name: &str) -> Result<PathBuf, StatusError> {
+    let Some((root_name, relative)) = name.split_once(':') else {
+        let path = video_path(&SafePath::new(name)?);
+        ensure_inside(Path::new(VIDEO_PATH), &path)?;
+        return Ok(path);
+    };
+
+    let root = root(root_name).ok_or_else(|| {
+        StatusError::new(
+            StatusCode::NOT_FOUND,
+            format!("no library root named '{root_name}'"),
+        )
+    })?;
+
+    let path = root.path.join(SafePath::new(relative)?);
+    ensure_inside(&root.path, &path)?;
+    Ok(path)
+}
+
+// for anything that would change files in a root
+pub fn ensure_writable(path: &Path) -> Result<(), StatusError> {
+    match split_root(path) {
+        Some((root, _)) if root.read_only => Err(StatusError::new(
+            StatusCode::FORBIDDEN,
+            format!("'{}' is read only", root.name),
+        )),
+        _ => Ok(()),
+    }
+}
+
+#[must_use]
+pub fn is_media(path: &Path) -> bool {
+    path.extension()
+        .and_then(OsStr::to_str)
+        .is_some_and(|ext| MEDIA_EXTENSIONS.iter().any(|m| m.eq_ignore_ascii_case(ext)))
+}
+
+#[derive(Default)]
+pub struct LibraryEntries {
+    pub files: Vec<PathBuf>,
+    pub folders: Vec<PathBuf>,
+}
+
+// walks the videos under root, skipping hidden files & folders and anything that isn't media
+// results are sorted so folders group together in listings
+pub async fn walk(root: &Path) -> std::io::Result<LibraryEntries> {
+    walk_matching(root, is_media).await
+}
+
+// every file in a mirror folder, e.x. thumbs/, whatever its extension
+pub async fn walk_generated(mirror: &Path) -> std::io::Result<LibraryEntries> {
+    walk_matching(mirror, |_| true).await
+}
+
+async fn walk_matching(root: &Path, keep: fn(&Path) -> bool) -> std::io::Result<LibraryEntries> {
+    let mut entries = LibraryEntries::default();
+    let mut pending = vec![root.to_path_buf()];
+
+    // no recursion here since async fns can't recurse without boxing
+    while let Some(dir) = pending.pop() {
+        let mut read_dir = fs::read_dir(&dir).await?;
+        while let Some(entry) = read_dir.next_entry().await? {
+            if entry.file_name().to_string_lossy().starts_with('.') {
+                continue;
+            }
+
+            let file_type = entry.file_type().await?;
+            if file_type.is_dir() {
+                pending.push(entry.path());
+                entries.folders.push(entry.path());
+            } else if file_type.is_file() && keep(&entry.path()) {
+                entries.files.push(entry.path());
+            }
+        }
+    }
+
+    entries.files.sort();
+    entries.folders.sort();
+
+    Ok(entries)
+}
+
+// whatever walk skips, e.x. hidden files, symlinks & subtitles, so deleting a folder can't take them along unseen
+pub async fn unlisted_files(root: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut unlisted = vec![];
+    let mut pending = vec![root.to_path_buf()];
+
+    while let Some(dir) = pending.pop() {
+        let mut read_dir = fs::read_dir(&dir).await?;
+        while let Some(entry) = read_dir.next_entry().await? {
+            let file_type = entry.file_type().await?;
+            if file_type.is_dir() {
+                pending.push(entry.path());
+            } else if !file_type.is_file()
+                || entry.file_name().to_string_lossy().starts_with('.')
+                || !is_media(&entry.path())
+            {
+                unlisted.push(entry.path());
+            }
+        }
+    }
+
+    unlisted.sort();
+    Ok(unlisted)
+}
+
+// every root merged together, one that's missing (e.x. an unmounted nas) is skipped
+pub async fn walk_all() -> std::io::Result<LibraryEntries> {
+    let mut entries = LibraryEntries::default();
+    for root in roots() {
+        match walk(&root.path).await {
+            Ok(walked) => {
+                entries.files.extend(walked.files);
+                entries.folders.extend(walked.folders);
+            }
+            Err(e) if !root.name.is_empty() => {
+                warn!("skipping library root '{}': {e}", root.name);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(entries)
+}
+
+// rename doesn't work across filesystems, e.x. from a nas mount into trash/
+pub async fn move_file(from: &Path, to: &Path) -> std::io::Result<()> {
+    if fs::rename(from, to).await.is_ok() {
+        return Ok(());
+    }
+
+    fs::copy(from, to).await?;
+    fs::remove_file(from).await
+}
+
+pub async fn create_parent(path: &Path) -> std::io::Result<()> {
+    match path.parent() {
+        Some(parent) => fs::create_dir_all(parent).await,
+        None => Ok(()),
+    }
+}
+
+// moves a video inside of its root along with everything generated for it, returns how many playlists changed
+pub async fn rename(old_path: &Path, new_path: &Path) -> anyhow::Result<usize> {
+    info!(
+        "renaming video '{}' -> '{}'",
+        old_path.display(),
+        new_path.display()
+    );
+
+    // renaming into a folder that doesn't exist yet creates it
+    create_parent(new_path).await?;
+    fs::rename(old_path, new_path)
+        .await
+        .context("failed to rename video")?;
+
+    let (old_thumb, new_thumb) = (thumbnail_path(old_path), thumbnail_path(new_path));
+    if old_thumb.is_file() {
+        create_parent(&new_thumb).await?;
+        if let Err(e) = fs::rename(&old_thumb, &new_thumb).await {
+            // put the video back so the thumbnail still lines up with it
+            let _ = fs::rename(new_path, old_path).await;
+            bail!("failed to rename thumbnail: {e}");
+        }
+    }
+
+    // the thumbnail is first & already moved, the rest can just be regenerated if this fails
+    for (old, new) in generated_files(old_path)
+        .into_iter()
+        .zip(generated_files(new_path))
+        .skip(1)
+    {
+        if old.is_file() {
+            create_parent(&new).await?;
+            if let Err(e) = fs::rename(&old, &new).await {
+                warn!("failed to rename '{}': {e}", old.display());
+            }
+        }
+    }
+
+    let changed = playlist::replace_video(old_path, Some(new_path)).await?;
+    metadata::rename(old_path, new_path).await?;
+    info!("renamed video, updated {changed} playlists");
+
+    Ok(changed)
+}
+
+// everything generated from a video, same order as MIRRORS
+#[must_use]
+pub fn generated_files(video: &Path) -> [PathBuf; 3] {
+    [
+        thumbnail_path(video),
+        optimized_path(video),
+        preview_path(video),
+    ]
+}
+
+pub async fn remove_generated(video: &Path) {
+    for file in generated_files(video) {
+        let _ = fs::remove_file(file).await;
+    }
+}
+
+// uploads/folder -> thumbs/folder, optimized/folder...
+pub async fn remove_generated_folder(folder: &Path) {
+    // a root itself would take the whole mirror folder with it
+    if split_root(folder).is_none_or(|(_, relative)| relative.as_os_str().is_empty()) {
+        return;
+    }
+
+    let relative = mirror_relative(folder);
+    for mirror in MIRRORS {
+        let _ = fs::remove_dir_all(Path::new(mirror).join(&relative)).await;
+    }
+}
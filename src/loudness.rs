@@ -0,0 +1,92 @@
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use tokio::process::Command;
+
+use crate::{config::config, metadata::VideoMeta};
+
+// don't blow up a basically silent clip into pure noise
+const MAX_BOOST_DB: f64 = 12.0;
+const MAX_CUT_DB: f64 = 30.0;
+const MIN_FACTOR: f32 = 0.25;
+const MAX_FACTOR: f32 = 2.0;
+// ebu r128 gates out anything quieter than this anyway
+const SILENCE_LUFS: f64 = -70.0;
+
+// the bit of loudnorm's json we care about, it gives numbers as strings
+#[derive(Deserialize)]
+struct LoudnormOutput {
+    input_i: String,
+}
+
+// integrated loudness in lufs
+pub async fn measure(path: &Path) -> Result<f64> {
+    // ffmpeg -hide_banner -nostats -i video.mp4 -vn -af loudnorm=print_format=json -f null -
+    let output = Command::new("ffmpeg")
+        .arg("-hide_banner")
+        .arg("-nostats")
+        .arg("-i")
+        .arg(path)
+        .arg("-vn")
+        .arg("-af")
+        .arg("loudnorm=print_format=json")
+        .arg("-f")
+        .arg("null")
+        .arg("-")
+        .kill_on_drop(true)
+        .output()
+        .await
+        .context("failed to run ffmpeg")?;
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if !output.status.success() {
+        bail!("ffmpeg failed: {}", stderr.trim());
+    }
+
+    // the json is the last thing printed, after all the usual stream info
+    let json = stderr
+        .rfind('{')
+        .map(|start| &stderr[start..])
+        .context("no loudnorm output")?;
+    let loudnorm = serde_json::from_str::<LoudnormOutput>(json.trim())
+        .context("failed to parse loudnorm output")?;
+
+    // '-inf' for silence
+    let loudness = loudnorm
+        .input_i
+        .trim()
+        .parse::<f64>()
+        .unwrap_or(SILENCE_LUFS);
+    Ok(if loudness.is_finite() {
+        loudness.max(SILENCE_LUFS)
+    } else {
+        SILENCE_LUFS
+    })
+}
+
+// db to add to get the video to the configured target, 0 if it hasn't been measured
+#[must_use]
+pub fn offset(meta: &VideoMeta) -> f64 {
+    meta.loudness.map_or(0.0, |loudness| {
+        (config().loudness_target - loudness).clamp(-MAX_CUT_DB, MAX_BOOST_DB)
+    })
+}
+
+// what to multiply vlc's linear gain by
+#[must_use]
+#[allow(clippy::cast_possible_truncation)]
+pub fn gain_factor(meta: &VideoMeta) -> f32 {
+    clamp_factor(10f64.powf(offset(meta) / 20.0) as f32)
+}
+
+// same range whether it goes into --gain for one video or rc's volume for playlist items
+// rc tops out at twice the normal volume, a broken measurement falls back to leaving it alone
+#[must_use]
+pub const fn clamp_factor(factor: f32) -> f32 {
+    if factor.is_finite() {
+        factor.clamp(MIN_FACTOR, MAX_FACTOR)
+    } else {
+        1.0
+    }
+}
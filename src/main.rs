@@ -1,7 +1,27 @@
+mod config;
+mod downloads;
+mod duplicates;
+mod events;
+mod jobs;
+mod library;
+mod loudness;
+mod maintenance;
 mod media_keys;
+mod metadata;
 mod playlist;
+mod playlist_formats;
+mod previews;
+mod probe;
+mod processing;
+mod resumable;
+mod safe_path;
+mod storage;
 mod thumbnails;
+mod transcode;
+mod trash;
+mod uploads;
 mod vlc_manager;
+mod watcher;
 mod web_manager;
 mod web_util;
 
@@ -10,6 +30,7 @@ use std::{
     fs::OpenOptions,
     path::{Path, PathBuf},
     sync::mpsc::Sender,
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
 use axum::{
@@ -20,8 +41,10 @@ use axum::{
 use media_keys::MediaKeyMessage;
 use once_cell::sync::OnceCell;
 use rust_embed::EmbeddedFile;
+use safe_path::SafePath;
 use simplelog::{
-    info, ColorChoice, CombinedLogger, Config, LevelFilter, TermLogger, TerminalMode, WriteLogger,
+    error, info, ColorChoice, CombinedLogger, Config, LevelFilter, TermLogger, TerminalMode,
+    WriteLogger,
 };
 use tokio::{fs, net::TcpListener};
 use tower_http::services::ServeDir;
@@ -29,14 +52,23 @@ use vlc_manager::{create_vlc_channel, VlcMessage};
 use web_manager::manager_router;
 use web_util::Asset;
 
-use crate::{media_keys::create_enigo_channel, thumbnails::generate_new_thumbs};
+use crate::media_keys::create_enigo_channel;
 
-// this is for me so im not sanitizing anything
-// don't use this with any public facing server or ur gonna get OWNED!!!!
+// names from requests are checked so they can't escape the folders (see safe_path.rs)
+// but there's still no auth, don't use this with any public facing server or ur gonna get OWNED!!!!
 
 pub const VIDEO_PATH: &str = "uploads/";
 pub const THUMB_PATH: &str = "thumbs/";
 pub const PLAYLIST_PATH: &str = "playlists/";
+pub const OPTIMIZED_PATH: &str = "optimized/";
+pub const PREVIEW_PATH: &str = "previews/";
+pub const BROKEN_PATH: &str = "broken/";
+pub const TRASH_PATH: &str = "trash/";
+pub const TMP_PATH: &str = "tmp/";
+pub const RESUMABLE_PATH: &str = "tmp/resumable/";
+pub const DOWNLOAD_PATH: &str = "tmp/downloads/";
+
+pub const HOUR: Duration = Duration::from_secs(60 * 60);
 
 const DEFAULT_FLAGS: &[&str] = &[
     "--fullscreen",
@@ -77,12 +109,57 @@ async fn main() {
     .await
     .unwrap();
 
-    for path in &[VIDEO_PATH, THUMB_PATH, PLAYLIST_PATH] {
+    for path in &[
+        VIDEO_PATH,
+        THUMB_PATH,
+        PLAYLIST_PATH,
+        OPTIMIZED_PATH,
+        PREVIEW_PATH,
+        TRASH_PATH,
+        TMP_PATH,
+        RESUMABLE_PATH,
+    ] {
         let _ = fs::create_dir(path).await;
     }
 
-    info!("checking if need to generate new thumbnails...");
-    let _ = generate_new_thumbs().await;
+    let _ = uploads::clean_temp().await;
+    downloads::clean_temp().await;
+    if let Err(e) = metadata::load().await {
+        error!("failed to load metadata, continuing without it: {e:?}");
+    }
+
+    config::load().await;
+    if let Err(e) = jobs::load().await {
+        error!("failed to load jobs, starting with an empty queue: {e:?}");
+    }
+
+    jobs::start();
+    trash::start_purge();
+    resumable::start_expiry();
+    if config::config().watch_library {
+        if let Err(e) = watcher::start() {
+            error!("failed to watch the library, restart to pick up copied in files: {e:?}");
+        }
+    }
+
+    // in the background so the server is up straight away, the jobs themselves are limited by job_concurrency
+    drop(tokio::task::spawn(async {
+        if let Err(e) = thumbnails::migrate_names().await {
+            error!("failed to rename old thumbnails: {e:?}");
+        }
+
+        let config = config::config();
+        if config.sweep_on_startup {
+            if let Err(e) = maintenance::sweep(config.sweep_fix_on_startup).await {
+                error!("library sweep failed: {e:?}");
+            }
+        }
+
+        info!("checking for videos that need processing...");
+        if let Err(e) = processing::process_missing().await {
+            error!("failed to check for unprocessed videos: {e:?}");
+        }
+    }));
 
     let flags: Vec<String> = if let Ok(flag_file) = fs::read_to_string("flags.txt").await {
         flag_file
@@ -101,6 +178,7 @@ async fn main() {
     let (vlc_channel, enigo_channel) = (create_vlc_channel(), create_enigo_channel());
     let app = manager_router()
         .nest_service("/thumbs", ServeDir::new(THUMB_PATH))
+        .nest_service("/previews", ServeDir::new(PREVIEW_PATH))
         .route("/", get(index))
         .route("/styles", get(styles))
         .route("/script", get(script))
@@ -125,10 +203,34 @@ pub struct AppState {
 
 #[must_use]
 #[inline]
-pub fn video_path(path: &str) -> PathBuf {
+pub fn video_path(path: &SafePath) -> PathBuf {
     Path::new(VIDEO_PATH).join(path)
 }
 
+// opposite of library::resolve, uploads/folder/a.mp4 -> folder/a.mp4, /mnt/nas/a.mp4 -> nas:a.mp4
+// none for files SafePath wouldn't accept, e.x. 'nas:a.mp4' in uploads/ would read back as the nas one
+// those can't be played or renamed by name, /maintenance flags them instead
+#[must_use]
+pub fn video_name(path: &Path) -> Option<String> {
+    let (root, relative) = library::split_root(path)?;
+    let relative = SafePath::new(relative.to_str()?).ok()?;
+
+    Some(if root.name.is_empty() {
+        relative.to_string()
+    } else {
+        format!("{}:{relative}", root.name)
+    })
+}
+
+// unix seconds
+#[must_use]
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
 type EmbeddedData = Cow<'static, [u8]>;
 
 async fn index() -> Html<EmbeddedData> {
@@ -0,0 +1,409 @@
+use std::{
+    collections::HashSet,
+    io,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+use axum::{http::StatusCode, routing::get, Json, Router};
+use serde::{Deserialize, Serialize};
+use simplelog::{info, warn};
+use tokio::{fs, process::Command};
+
+use crate::{
+    jobs::{self, JobContext, JobTask},
+    library, metadata, now,
+    playlist::{self, Playlist},
+    probe,
+    safe_path::SafePath,
+    video_name,
+    web_util::{StatusError, WebResult},
+    AppState, BROKEN_PATH,
+};
+
+// a broken file can make ffmpeg print an error for every frame
+const MAX_DECODE_ERROR_LINES: usize = 10;
+
+// finds stuff that got out of sync, mostly from files being changed outside of the server
+// GET /maintenance just reports, POST /maintenance fixes everything it can too
+// POST /maintenance/decode fully decodes videos in the background, GET lists the ones that failed
+
+#[derive(Serialize, Default)]
+pub struct Report {
+    fixed: bool,
+    // thumbnails/previews/optimized versions whose video is gone
+    orphan_files: Vec<PathBuf>,
+    orphan_metadata: Vec<PathBuf>,
+    dangling_entries: Vec<DanglingEntry>,
+    empty_playlists: Vec<PathBuf>,
+    // moved to broken/ when fixing, they'd just fail to play
+    unreadable: Vec<Unreadable>,
+    // files with names that can't be asked for, e.x. with a colon in them, renamed when fixing
+    invalid_names: Vec<InvalidName>,
+}
+
+#[derive(Serialize)]
+struct DanglingEntry {
+    playlist: PathBuf,
+    video: PathBuf,
+}
+
+#[derive(Serialize)]
+struct InvalidName {
+    video: PathBuf,
+    // none if it wasn't or couldn't be renamed
+    renamed_to: Option<PathBuf>,
+}
+
+#[derive(Serialize)]
+struct Unreadable {
+    video: PathBuf,
+    error: String,
+}
+
+impl Report {
+    const fn problems(&self) -> usize {
+        self.orphan_files.len()
+            + self.orphan_metadata.len()
+            + self.dangling_entries.len()
+            + self.empty_playlists.len()
+            + self.unreadable.len()
+            + self.invalid_names.len()
+    }
+}
+
+pub async fn sweep(fix: bool) -> Result<Report> {
+    let mut report = Report {
+        fixed: fix,
+        ..Default::default()
+    };
+
+    // an unmounted nas would look like every video on it got deleted
+    if let Some(root) = library::roots().find(|root| !root.path.is_dir()) {
+        bail!(
+            "library root '{}' isn't available, not sweeping",
+            root.path.display()
+        );
+    }
+
+    // first so everything after it already treats them as gone
+    unreadable_media(&mut report, fix).await?;
+    invalid_names(&mut report, fix).await?;
+
+    let videos = library::walk_all()
+        .await?
+        .files
+        .into_iter()
+        .collect::<HashSet<_>>();
+
+    orphan_files(&mut report, &videos, fix).await?;
+    orphan_metadata(&mut report, &videos, fix).await?;
+    playlists(&mut report, &videos, fix).await?;
+
+    info!(
+        "library sweep found {} problems{}",
+        report.problems(),
+        if fix { " and fixed them" } else { "" }
+    );
+
+    Ok(report)
+}
+
+// only probes things that were never probed successfully, anything else has been readable before
+async fn unreadable_media(report: &mut Report, fix: bool) -> Result<()> {
+    for video in library::walk_all().await?.files {
+        if metadata::get(&video).await.probe.is_some() {
+            continue;
+        }
+
+        let Err(e) = probe::probe(&video).await else {
+            continue;
+        };
+
+        // ffprobe itself couldn't run, every file would look broken
+        if e.downcast_ref::<io::Error>().is_some() {
+            bail!("couldn't check media: {e:#}");
+        }
+
+        warn!("'{}' is unreadable: {e}", video.display());
+        if fix && library::ensure_writable(&video).is_ok() {
+            quarantine(&video).await?;
+        }
+
+        report.unreadable.push(Unreadable {
+            video,
+            error: e.to_string(),
+        });
+    }
+
+    Ok(())
+}
+
+// anything video_name gives up on, the ui never lists them so this is the only place they show up
+async fn invalid_names(report: &mut Report, fix: bool) -> Result<()> {
+    for video in library::walk_all().await?.files {
+        if video_name(&video).is_some() {
+            continue;
+        }
+
+        let Some((root, relative)) = library::split_root(&video) else {
+            continue;
+        };
+
+        warn!("'{}' has a name that can't be used", video.display());
+        let renamed_to = match SafePath::sanitize(&relative.to_string_lossy()) {
+            Some(name) if fix && library::ensure_writable(&video).is_ok() => {
+                let new_path = root.path.join(&name);
+                if new_path.exists() {
+                    warn!("not renaming, '{}' already exists", new_path.display());
+                    None
+                } else {
+                    library::rename(&video, &new_path).await?;
+                    Some(new_path)
+                }
+            }
+            _ => None,
+        };
+
+        report.invalid_names.push(InvalidName { video, renamed_to });
+    }
+
+    Ok(())
+}
+
+// uploads/folder/a.mp4 -> broken/folder/a.mp4, not deleted in case it's a false alarm
+async fn quarantine(video: &Path) -> Result<()> {
+    let broken = Path::new(BROKEN_PATH).join(library::mirror_relative(video));
+    if let Some(parent) = broken.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+
+    library::move_file(video, &broken).await?;
+    info!("moved '{}' to '{}'", video.display(), broken.display());
+
+    library::remove_generated(video).await;
+    playlist::replace_video(video, None).await?;
+    metadata::remove(video).await?;
+
+    Ok(())
+}
+
+async fn orphan_files(report: &mut Report, videos: &HashSet<PathBuf>, fix: bool) -> Result<()> {
+    let expected = videos
+        .iter()
+        .flat_map(|video| library::generated_files(video))
+        .collect::<HashSet<_>>();
+
+    for mirror in library::MIRRORS {
+        for file in library::walk_generated(Path::new(mirror)).await?.files {
+            if expected.contains(&file) {
+                continue;
+            }
+
+            if fix {
+                let _ = fs::remove_file(&file).await;
+            }
+
+            report.orphan_files.push(file);
+        }
+    }
+
+    Ok(())
+}
+
+async fn orphan_metadata(report: &mut Report, videos: &HashSet<PathBuf>, fix: bool) -> Result<()> {
+    for video in metadata::videos().await {
+        if videos.contains(&video) {
+            continue;
+        }
+
+        if fix {
+            metadata::remove(&video).await?;
+        }
+
+        report.orphan_metadata.push(video);
+    }
+
+    Ok(())
+}
+
+async fn playlists(report: &mut Report, videos: &HashSet<PathBuf>, fix: bool) -> Result<()> {
+    for path in playlist::playlist_files().await? {
+        let playlist = playlist::read_playlist(&path).await?;
+
+        let (existing, dangling): (Vec<_>, Vec<_>) = playlist
+            .videos
+            .iter()
+            .cloned()
+            .partition(|v| videos.contains(v));
+
+        for video in &dangling {
+            report.dangling_entries.push(DanglingEntry {
+                playlist: path.clone(),
+                video: video.clone(),
+            });
+        }
+
+        if existing.is_empty() {
+            report.empty_playlists.push(path.clone());
+        }
+
+        // writing an empty playlist removes it
+        if fix && (!dangling.is_empty() || existing.is_empty()) {
+            playlist::write_playlist(&Playlist {
+                videos: existing,
+                ..playlist
+            })
+            .await?;
+        }
+    }
+
+    Ok(())
+}
+
+// the sweep only checks that ffprobe can read the header, this decodes every frame
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DecodeCheck {
+    // unix seconds
+    pub checked_at: u64,
+    // what ffmpeg complained about, none if it decoded cleanly
+    pub error: Option<String>,
+}
+
+// runs as a job since it takes about as long as the video would to play
+pub async fn decode_check(video: &Path, ctx: &JobContext) -> Result<()> {
+    if !video.is_file() {
+        bail!("'{}' doesn't exist anymore", video.display());
+    }
+
+    // ffmpeg -nostdin -v error -i video.mp4 -f null -
+    let output = Command::new("ffmpeg")
+        .arg("-nostdin")
+        .arg("-v")
+        .arg("error")
+        .arg("-i")
+        .arg(video)
+        .arg("-f")
+        .arg("null")
+        .arg("-")
+        .kill_on_drop(true)
+        .output()
+        .await
+        .context("failed to run ffmpeg")?;
+
+    let errors = String::from_utf8_lossy(&output.stderr)
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .take(MAX_DECODE_ERROR_LINES)
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    let error = if !errors.is_empty() {
+        Some(errors)
+    } else if output.status.success() {
+        None
+    } else {
+        Some(format!("ffmpeg exited with {}", output.status))
+    };
+
+    match &error {
+        Some(error) => ctx.log(format!("failed to decode: {error}")).await,
+        None => ctx.log("decoded cleanly").await,
+    }
+
+    metadata::update(video, |meta| {
+        meta.decode_check = Some(DecodeCheck {
+            checked_at: now(),
+            error,
+        });
+    })
+    .await
+}
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/maintenance", get(check).post(fix))
+        .route(
+            "/maintenance/decode",
+            get(corrupt).post(queue_decode_checks),
+        )
+}
+
+async fn check() -> WebResult<Json<Report>> {
+    Ok(Json(sweep(false).await?))
+}
+
+async fn fix() -> WebResult<Json<Report>> {
+    Ok(Json(sweep(true).await?))
+}
+
+#[derive(Deserialize)]
+struct DecodeRequest {
+    // everything if none
+    video_name: Option<String>,
+}
+
+#[derive(Serialize)]
+struct QueuedResponse {
+    queued: usize,
+}
+
+async fn queue_decode_checks(
+    Json(DecodeRequest { video_name }): Json<DecodeRequest>,
+) -> WebResult<(StatusCode, Json<QueuedResponse>)> {
+    let videos = match video_name {
+        Some(name) => {
+            let video = library::resolve(&name)?;
+            if !video.is_file() {
+                return Err(StatusError::new(StatusCode::NOT_FOUND, "video not found").into());
+            }
+
+            vec![video]
+        }
+        None => library::walk_all().await?.files,
+    };
+
+    jobs::enqueue_all(videos.iter().map(|video| JobTask::DecodeCheck {
+        video: video.clone(),
+    }))
+    .await;
+
+    info!("queued {} decode checks", videos.len());
+
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(QueuedResponse {
+            queued: videos.len(),
+        }),
+    ))
+}
+
+#[derive(Serialize)]
+struct Corrupt {
+    video: String,
+    error: String,
+    checked_at: u64,
+}
+
+// everything the last decode check found problems with
+async fn corrupt() -> Json<Vec<Corrupt>> {
+    let mut corrupt = vec![];
+    for video in metadata::videos().await {
+        let Some(DecodeCheck {
+            checked_at,
+            error: Some(error),
+        }) = metadata::get(&video).await.decode_check
+        else {
+            continue;
+        };
+
+        corrupt.push(Corrupt {
+            video: video_name(&video).unwrap_or_else(|| video.display().to_string()),
+            error,
+            checked_at,
+        });
+    }
+
+    Json(corrupt)
+}
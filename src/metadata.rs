@@ -0,0 +1,169 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use simplelog::{info, warn};
+use tokio::{
+    fs,
+    sync::{Mutex, MutexGuard},
+};
+
+use crate::{
+    maintenance::DecodeCheck, previews::Preview, probe::ProbeInfo, thumbnails::ThumbnailChoice,
+    transcode::Optimized,
+};
+
+const METADATA_FILE: &str = "metadata.json";
+
+// per video info that can't be read off the file itself
+// keyed by the same paths playlists use, e.x. 'uploads/ambient/rain.mp4'
+#[derive(Serialize, Deserialize, Default, Clone, Debug)]
+pub struct VideoMeta {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub probe: Option<ProbeInfo>,
+    // none until transcoding has looked at it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub optimized: Option<Optimized>,
+    // integrated lufs of whatever actually gets played, none until measured
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub loudness: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preview: Option<Preview>,
+    // none if ffmpeg gets to pick
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thumbnail: Option<ThumbnailChoice>,
+    // none until POST /maintenance/decode has gone through it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub decode_check: Option<DecodeCheck>,
+    // of what was uploaded, kept when transcoding replaces the file so re-uploading it is still caught
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    // unix seconds, for storage retention
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_played: Option<u64>,
+}
+
+// tokio mutex so a save can't get overtaken by an older one
+static METADATA: Lazy<Mutex<HashMap<PathBuf, VideoMeta>>> = Lazy::new(Mutex::default);
+
+pub async fn load() -> Result<()> {
+    let Ok(data) = fs::read(METADATA_FILE).await else {
+        info!("no {METADATA_FILE}, starting fresh");
+        return Ok(());
+    };
+
+    let loaded = match serde_json::from_slice::<HashMap<PathBuf, VideoMeta>>(&data) {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            // keep it around instead of overwriting it on the next save
+            let _ = fs::rename(METADATA_FILE, format!("{METADATA_FILE}.broken")).await;
+            return Err(e).with_context(|| format!("failed to parse {METADATA_FILE}"));
+        }
+    };
+
+    info!("loaded metadata for {} videos", loaded.len());
+    *METADATA.lock().await = loaded;
+
+    Ok(())
+}
+
+pub async fn get(video: &Path) -> VideoMeta {
+    METADATA
+        .lock()
+        .await
+        .get(video)
+        .cloned()
+        .unwrap_or_default()
+}
+
+// every video there's metadata for, even if it's gone
+pub async fn videos() -> Vec<PathBuf> {
+    METADATA.lock().await.keys().cloned().collect()
+}
+
+pub async fn update<F>(video: &Path, f: F) -> Result<()>
+where
+    F: FnOnce(&mut VideoMeta),
+{
+    let mut metadata = METADATA.lock().await;
+    f(metadata.entry(video.to_path_buf()).or_default());
+    save(metadata).await
+}
+
+// same as update but saved once for all of them
+pub async fn update_all<F>(videos: &[PathBuf], f: F) -> Result<()>
+where
+    F: Fn(&mut VideoMeta),
+{
+    let mut metadata = METADATA.lock().await;
+    for video in videos {
+        f(metadata.entry(video.clone()).or_default());
+    }
+    save(metadata).await
+}
+
+pub async fn rename(old: &Path, new: &Path) -> Result<()> {
+    rekey(|path| {
+        if path == old {
+            return Some(new.to_path_buf());
+        }
+
+        Some(path.to_path_buf())
+    })
+    .await
+}
+
+pub async fn rename_folder(old: &Path, new: &Path) -> Result<()> {
+    rekey(|path| {
+        let path = path
+            .strip_prefix(old)
+            .map_or_else(|_| path.to_path_buf(), |rest| new.join(rest));
+
+        Some(path)
+    })
+    .await
+}
+
+pub async fn remove(video: &Path) -> Result<()> {
+    rekey(|path| (path != video).then(|| path.to_path_buf())).await
+}
+
+pub async fn remove_folder(folder: &Path) -> Result<()> {
+    rekey(|path| (!path.starts_with(folder)).then(|| path.to_path_buf())).await
+}
+
+// moves every entry to whatever f returns, none drops it
+async fn rekey<F>(f: F) -> Result<()>
+where
+    F: Fn(&Path) -> Option<PathBuf>,
+{
+    let mut metadata = METADATA.lock().await;
+    let rekeyed = metadata
+        .drain()
+        .filter_map(|(path, meta)| Some((f(&path)?, meta)))
+        .collect();
+    *metadata = rekeyed;
+
+    save(metadata).await
+}
+
+// takes the guard so it's held until the file is written
+async fn save(metadata: MutexGuard<'_, HashMap<PathBuf, VideoMeta>>) -> Result<()> {
+    let data = serde_json::to_vec_pretty(&*metadata)?;
+
+    // same trick as playlists, never leave a half written file around
+    let tmp_path = format!("{METADATA_FILE}.tmp");
+    fs::write(&tmp_path, data).await?;
+    if let Err(e) = fs::rename(&tmp_path, METADATA_FILE).await {
+        warn!("failed to save metadata: {e}");
+        return Err(e.into());
+    }
+
+    Ok(())
+}
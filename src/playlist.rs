@@ -1,32 +1,247 @@
-use std::path::PathBuf;
+use std::{
+    collections::HashMap,
+    env,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
 
+use axum::http::StatusCode;
 use futures::TryStreamExt;
+use simplelog::info;
 use tokio::fs;
 use tokio_stream::{wrappers::ReadDirStream, StreamExt};
 
-use crate::{video_path, PLAYLIST_PATH};
+use crate::{
+    loudness, metadata,
+    safe_path::SafePath,
+    transcode::playback_path,
+    uploads::ConflictPolicy,
+    vlc_manager::{PlaybackMode, PlaylistItem},
+    web_util::StatusError,
+    PLAYLIST_PATH, TMP_PATH,
+};
 
-// should end with .vlc
-pub fn playlist_path(playlist_file: &str) -> PathBuf {
-    let mut path = PathBuf::from(PLAYLIST_PATH);
-    path.push(playlist_file);
+const NOW_PLAYING_FILE: &str = "now_playing.m3u";
+const MAX_NAME_LEN: usize = 256;
 
-    path
-}
+// playlists are saved as '<id>.vlc', one video per line with a few '# key: value' headers on top
+// e.x. '# name: lo_fi beats', '# description: ...', '# mode: in_order'
+// the id is picked once from the name & never changes, the name can be anything
 
 pub struct Playlist {
     pub videos: Vec<PathBuf>,
     pub path: PathBuf,
+    pub name: String,
+    pub description: Option<String>,
+    // how it plays when the request doesn't say, none falls back to shuffle
+    pub mode: Option<PlaybackMode>,
 }
 
 impl Playlist {
-    pub fn new(name: &str, videos: Vec<String>) -> Self {
-        let path = playlist_path(name);
+    // gets a fresh id, nothing is written until write_playlist
+    pub fn new(name: String, videos: Vec<PathBuf>) -> Self {
         Self {
-            videos: videos.into_iter().map(|f| video_path(&f)).collect(),
-            path,
+            videos,
+            path: free_path(&name),
+            name,
+            description: None,
+            mode: None,
+        }
+    }
+
+    // what requests & the ui refer to it by, e.x. 'lo-fi-beats'
+    pub fn id(&self) -> String {
+        self.path
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned()
+    }
+}
+
+pub fn validate_name(name: &str) -> Result<(), StatusError> {
+    if name.trim().is_empty() {
+        return Err(StatusError::new(
+            StatusCode::BAD_REQUEST,
+            "playlist name can't be empty",
+        ));
+    }
+
+    if name.chars().count() > MAX_NAME_LEN {
+        return Err(StatusError::new(
+            StatusCode::BAD_REQUEST,
+            "playlist name is too long",
+        ));
+    }
+
+    Ok(())
+}
+
+// 'Lo-Fi Beats 🌙' -> 'lo-fi-beats', ascii so it's a safe file name everywhere
+fn slug(name: &str) -> String {
+    let slug = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_lowercase()
+            } else {
+                '-'
+            }
+        })
+        .collect::<String>();
+
+    let slug = slug
+        .split('-')
+        .filter(|part| !part.is_empty())
+        .take(8)
+        .collect::<Vec<_>>()
+        .join("-");
+
+    // e.x. names that are all emoji
+    // 'new' & 'none' are what the ui's playlist picker uses for its own options
+    match slug.as_str() {
+        "" => "playlist".to_string(),
+        "new" | "none" => format!("{slug}-2"),
+        _ => slug,
+    }
+}
+
+// 'lo-fi-beats.vlc', or 'lo-fi-beats-2.vlc' if a different name already slugged to that
+fn free_path(name: &str) -> PathBuf {
+    let slug = slug(name);
+    let mut path = Path::new(PLAYLIST_PATH).join(format!("{slug}.vlc"));
+
+    let mut i = 2;
+    while path.exists() {
+        path = Path::new(PLAYLIST_PATH).join(format!("{slug}-{i}.vlc"));
+        i += 1;
+    }
+
+    path
+}
+
+// none if it can't be an id at all, e.x. has a slash in it
+fn id_path(id: &str) -> Option<PathBuf> {
+    let file = SafePath::file_name(&format!("{id}.vlc")).ok()?;
+    Some(Path::new(PLAYLIST_PATH).join(file))
+}
+
+// playlists from before names were stored, 'lo_fi' -> 'lo fi' like the ui used to show them
+pub fn legacy_name(path: &Path) -> String {
+    path.file_stem()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .replace('_', " ")
+}
+
+pub async fn by_id(id: &str) -> std::io::Result<Option<Playlist>> {
+    match id_path(id).filter(|path| path.is_file()) {
+        Some(path) => read_playlist(&path).await.map(Some),
+        None => Ok(None),
+    }
+}
+
+// for requests that can give either, the id wins if both are there
+// kept apart so a name that happens to look like some other playlist's id can't get mixed up
+pub async fn by_id_or_name(
+    id: Option<&str>,
+    name: Option<&str>,
+) -> std::io::Result<Option<Playlist>> {
+    match (id, name) {
+        (Some(id), _) => by_id(id).await,
+        (None, Some(name)) => named(name, None).await,
+        (None, None) => Ok(None),
+    }
+}
+
+// the playlist that has this name, other than `except`
+pub async fn named(name: &str, except: Option<&Path>) -> std::io::Result<Option<Playlist>> {
+    for path in playlist_files().await? {
+        if except == Some(path.as_path()) {
+            continue;
+        }
+
+        let playlist = read_playlist(&path).await?;
+        if playlist.name == name {
+            return Ok(Some(playlist));
+        }
+    }
+
+    Ok(None)
+}
+
+// makes sure no other playlist has the name so looking one up by name stays unambiguous
+// 'chill' -> 'chill (1)' with the rename policy, overwrite deletes the playlist that had it
+pub async fn claim_name(
+    name: &str,
+    conflict: ConflictPolicy,
+    except: Option<&Path>,
+) -> anyhow::Result<String> {
+    validate_name(name)?;
+    let Some(existing) = named(name, except).await? else {
+        return Ok(name.to_string());
+    };
+
+    match conflict {
+        ConflictPolicy::Overwrite => {
+            fs::remove_file(&existing.path).await?;
+            info!("replaced playlist '{name}' ({})", existing.id());
+            Ok(name.to_string())
+        }
+        ConflictPolicy::Reject => Err(StatusError::new(
+            StatusCode::CONFLICT,
+            format!("playlist '{name}' already exists"),
+        )
+        .into()),
+        ConflictPolicy::Rename => {
+            let mut i = 1;
+            loop {
+                let candidate = format!("{name} ({i})");
+                if named(&candidate, except).await?.is_none() {
+                    return Ok(candidate);
+                }
+
+                i += 1;
+            }
+        }
+    }
+}
+
+// headers are one line each, so newlines & backslashes get escaped to keep any name intact
+fn escape_header(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
+fn unescape_header(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('n') => unescaped.push('\n'),
+            Some('r') => unescaped.push('\r'),
+            Some(other) => unescaped.push(other),
+            None => unescaped.push('\\'),
         }
     }
+
+    unescaped
+}
+
+// '# name: lo fi' -> 'lo fi' for key 'name'
+fn header<'a>(line: &'a str, key: &str) -> Option<&'a str> {
+    let value = line
+        .strip_prefix("# ")?
+        .strip_prefix(key)?
+        .strip_prefix(':')?;
+    Some(value.strip_prefix(' ').unwrap_or(value))
 }
 
 pub async fn playlists() -> anyhow::Result<Vec<Playlist>> {
@@ -40,6 +255,10 @@ pub async fn playlists() -> anyhow::Result<Vec<Playlist>> {
             }
 
             let path = entry.path();
+            if path.extension().is_none_or(|ext| ext != "vlc") {
+                return Ok(None);
+            }
+
             let playlist = read_playlist(&path).await?;
             if playlist.videos.is_empty() {
                 return Ok(None);
@@ -53,34 +272,283 @@ pub async fn playlists() -> anyhow::Result<Vec<Playlist>> {
     Ok(playlists)
 }
 
+// every .vlc file, including empty ones unlike playlists()
+pub async fn playlist_files() -> std::io::Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    let mut read_dir = fs::read_dir(PLAYLIST_PATH).await?;
+    while let Some(entry) = read_dir.next_entry().await? {
+        let path = entry.path();
+        if entry.file_type().await?.is_file() && path.extension().is_some_and(|ext| ext == "vlc") {
+            files.push(path);
+        }
+    }
+
+    files.sort();
+    Ok(files)
+}
+
 pub async fn read_playlist(path: &PathBuf) -> std::io::Result<Playlist> {
     let playlist = fs::read_to_string(path).await?;
+    let headers = playlist.lines().take_while(|line| line.starts_with('#'));
+
+    let mut name = None;
+    let mut description = None;
+    let mut mode = None;
+    for line in headers {
+        if let Some(value) = header(line, "name") {
+            name = Some(unescape_header(value));
+        } else if let Some(value) = header(line, "description") {
+            description = Some(unescape_header(value));
+        } else if let Some(value) = header(line, "mode") {
+            // an unknown mode is ignored rather than making the whole playlist unreadable
+            mode = value.parse().ok();
+        }
+    }
+
     let files: Vec<PathBuf> = playlist
         .lines()
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
         .map(|line| line.strip_prefix("../").unwrap_or(line))
-        .filter(|line| !line.is_empty())
         .map(PathBuf::from)
         .collect();
 
     Ok(Playlist {
         videos: files,
+        name: name.unwrap_or_else(|| legacy_name(path)),
         path: path.to_owned(),
+        description,
+        mode,
     })
 }
 
 pub async fn write_playlist(playlist: &Playlist) -> std::io::Result<()> {
-    let _ = fs::remove_file(&playlist.path).await;
-
     if playlist.videos.is_empty() {
+        let _ = fs::remove_file(&playlist.path).await;
         return Ok(());
     }
 
-    let files = playlist
-        .videos
-        .iter()
-        .map(|p| format!("../{}", p.display()))
+    let mut lines = vec![format!("# name: {}", escape_header(&playlist.name))];
+    if let Some(description) = playlist.description.as_deref().filter(|d| !d.is_empty()) {
+        lines.push(format!("# description: {}", escape_header(description)));
+    }
+
+    if let Some(mode) = playlist.mode {
+        lines.push(format!("# mode: {}", mode.as_str()));
+    }
+
+    let files = lines
+        .into_iter()
+        .chain(playlist.videos.iter().map(|p| relative_to_playlists(p)))
         .collect::<Vec<_>>()
         .join("\n");
 
-    fs::write(&playlist.path, files).await
+    // write next to it then swap, so a crash never leaves a half written playlist
+    let tmp_path = playlist.path.with_extension("vlc.tmp");
+    fs::write(&tmp_path, files).await?;
+    fs::rename(&tmp_path, &playlist.path).await
+}
+
+// playlists are one folder down, roots outside of the library are absolute & stay that way
+fn relative_to_playlists(path: &Path) -> String {
+    if path.is_absolute() {
+        path.display().to_string()
+    } else {
+        format!("../{}", path.display())
+    }
+}
+
+pub struct NowPlaying {
+    pub path: PathBuf,
+    // by the absolute path vlc reports when it starts playing it
+    pub items: HashMap<PathBuf, PlaylistItem>,
+}
+
+// what actually gets handed to vlc, every video swapped for the version it should play
+// lives in tmp/ so the paths are relative the same way as saved playlists
+pub async fn now_playing(videos: &[PathBuf]) -> anyhow::Result<NowPlaying> {
+    let dir = env::current_dir()?;
+    let mut lines = vec!["#EXTM3U".to_string()];
+    let mut items = HashMap::new();
+    for video in videos {
+        let playback = playback_path(video).await;
+        // per item so loudness gets evened out mid playlist, the user's gain still applies on top
+        // not #EXTVLCOPT, vlc ignores unsafe options like audio-filter when they come from a playlist
+        let gain = loudness::gain_factor(&metadata::get(video).await);
+        items.insert(
+            dir.join(&playback),
+            PlaylistItem {
+                video: video.clone(),
+                gain,
+            },
+        );
+
+        lines.push(relative_to_playlists(&playback));
+    }
+
+    let path = Path::new(TMP_PATH).join(NOW_PLAYING_FILE);
+    let tmp_path = path.with_extension("m3u.tmp");
+    fs::write(&tmp_path, lines.join("\n")).await?;
+    fs::rename(&tmp_path, &path).await?;
+
+    Ok(NowPlaying { path, items })
+}
+
+// fisher-yates with a time seeded xorshift, doesn't need to be any good just different each time
+pub fn shuffle<T>(items: &mut [T]) {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let mut state = (now.as_secs() << 32) ^ u64::from(now.subsec_nanos()) | 1;
+
+    for i in (1..items.len()).rev() {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+
+        #[allow(clippy::cast_possible_truncation)]
+        items.swap(i, (state % (i as u64 + 1)) as usize);
+    }
+}
+
+// appends to the playlist with that name, creating it if there isn't one yet
+pub async fn add_video(name: &str, video: &Path) -> anyhow::Result<()> {
+    let mut playlist = if let Some(playlist) = named(name, None).await? {
+        playlist
+    } else {
+        validate_name(name)?;
+        Playlist::new(name.to_string(), vec![])
+    };
+
+    if !playlist.videos.iter().any(|v| v == video) {
+        playlist.videos.push(video.to_path_buf());
+        write_playlist(&playlist).await?;
+    }
+
+    Ok(())
+}
+
+// puts the video back at position (or the end if the playlist got shorter)
+// `removed` is what it looked like, recreated from that if it got removed for being empty
+pub async fn insert_video(removed: Playlist, position: usize, video: &Path) -> anyhow::Result<()> {
+    let mut playlist = if removed.path.is_file() {
+        read_playlist(&removed.path).await?
+    } else {
+        // something else could have taken the name in the meantime
+        let name = claim_name(&removed.name, ConflictPolicy::Rename, Some(&removed.path)).await?;
+        Playlist {
+            videos: vec![],
+            name,
+            ..removed
+        }
+    };
+
+    if playlist.videos.iter().any(|v| v == video) {
+        return Ok(());
+    }
+
+    let position = position.min(playlist.videos.len());
+    playlist.videos.insert(position, video.to_path_buf());
+    write_playlist(&playlist).await?;
+
+    Ok(())
+}
+
+// swaps every reference to `old` for `new` in every playlist, or drops it if `new` is none
+// returns how many playlists were touched
+pub async fn replace_video(old: &Path, new: Option<&Path>) -> anyhow::Result<usize> {
+    rewrite_videos(|video| {
+        if video != old {
+            return Some(video.to_path_buf());
+        }
+
+        new.map(Path::to_path_buf)
+    })
+    .await
+}
+
+// same as above but for everything inside of a folder
+pub async fn replace_folder(old: &Path, new: Option<&Path>) -> anyhow::Result<usize> {
+    rewrite_videos(|video| {
+        let Ok(rest) = video.strip_prefix(old) else {
+            return Some(video.to_path_buf());
+        };
+
+        new.map(|new| new.join(rest))
+    })
+    .await
+}
+
+// runs f over every video of every playlist, none drops the video
+async fn rewrite_videos<F>(f: F) -> anyhow::Result<usize>
+where
+    F: Fn(&Path) -> Option<PathBuf>,
+{
+    let mut changed = 0;
+    for mut playlist in playlists().await? {
+        let videos = playlist
+            .videos
+            .iter()
+            .filter_map(|v| f(v))
+            .collect::<Vec<_>>();
+
+        if videos == playlist.videos {
+            continue;
+        }
+
+        playlist.videos = videos;
+
+        info!("updated playlist '{}'", playlist.path.display());
+        write_playlist(&playlist).await?;
+        changed += 1;
+    }
+
+    Ok(changed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slugs() {
+        assert_eq!(slug("Lo-Fi Beats 🌙"), "lo-fi-beats");
+        assert_eq!(slug("  rain // night  "), "rain-night");
+        assert_eq!(slug("🌙🌙"), "playlist");
+        assert_eq!(slug("../../etc"), "etc");
+        assert_eq!(slug("a b c d e f g h i j"), "a-b-c-d-e-f-g-h");
+        assert_eq!(slug("New"), "new-2");
+        assert_eq!(slug("None!"), "none-2");
+        assert_eq!(slug("new none"), "new-none");
+    }
+
+    #[test]
+    fn headers_round_trip() {
+        for value in [
+            "lo fi",
+            "two\nlines",
+            "back\\slash\\n",
+            "crlf\r\n",
+            "trailing \\",
+        ] {
+            let line = format!("# name: {}", escape_header(value));
+            assert!(!line.contains('\n'));
+            assert_eq!(unescape_header(header(&line, "name").unwrap()), value);
+        }
+    }
+
+    #[test]
+    fn header_keys() {
+        assert_eq!(header("# name: lo fi", "name"), Some("lo fi"));
+        assert_eq!(header("# name:lo fi", "name"), Some("lo fi"));
+        assert_eq!(header("# names: lo fi", "name"), None);
+        assert_eq!(header("#name: lo fi", "name"), None);
+    }
+
+    #[test]
+    fn legacy_names() {
+        assert_eq!(
+            legacy_name(Path::new("playlists/lo_fi_beats.vlc")),
+            "lo fi beats"
+        );
+    }
 }
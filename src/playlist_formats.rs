@@ -0,0 +1,540 @@
+use std::{
+    env,
+    fmt::Write,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+use axum::{
+    body::Bytes,
+    extract::Query,
+    http::{header, StatusCode},
+    response::IntoResponse,
+    routing::{get, post},
+    Json, Router,
+};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, CONTROLS};
+use quick_xml::{escape::escape, events::Event, Reader};
+use serde::{Deserialize, Serialize};
+use simplelog::info;
+
+use crate::{
+    library, metadata,
+    playlist::{self, Playlist},
+    uploads::ConflictPolicy,
+    web_util::{StatusError, WebResult},
+    AppState,
+};
+
+// playlists in formats other players understand
+// GET /playlists/export?playlist_id=x&format=m3u8 downloads one
+// POST /playlists/import?playlist_name=x with the file as the body, entries get matched against the library
+
+// what has to be escaped in a file:// uri, '/' stays readable
+const URI_ESCAPE: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'[')
+    .add(b']')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}');
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    M3u,
+    M3u8,
+    Xspf,
+}
+
+impl Format {
+    const fn extension(self) -> &'static str {
+        match self {
+            Self::M3u => "m3u",
+            Self::M3u8 => "m3u8",
+            Self::Xspf => "xspf",
+        }
+    }
+
+    const fn content_type(self) -> &'static str {
+        match self {
+            Self::M3u => "audio/x-mpegurl",
+            Self::M3u8 => "application/vnd.apple.mpegurl",
+            Self::Xspf => "application/xspf+xml",
+        }
+    }
+
+    // for imports that don't say
+    fn guess(contents: &str) -> Self {
+        let start = contents.trim_start_matches('\u{feff}').trim_start();
+        if start.starts_with("<?xml") || start.starts_with("<playlist") {
+            Self::Xspf
+        } else {
+            Self::M3u8
+        }
+    }
+}
+
+// one line/track of an imported playlist before it's been matched
+struct Entry {
+    location: String,
+    title: Option<String>,
+}
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/playlists/export", get(export))
+        .route("/playlists/import", post(import))
+}
+
+#[derive(Deserialize)]
+struct ExportQuery {
+    playlist_id: Option<String>,
+    // by name like from before ids, used if there's no id
+    playlist_name: Option<String>,
+    format: Format,
+}
+
+async fn export(
+    Query(ExportQuery {
+        playlist_id,
+        playlist_name,
+        format,
+    }): Query<ExportQuery>,
+) -> WebResult<impl IntoResponse> {
+    let found = playlist::by_id_or_name(playlist_id.as_deref(), playlist_name.as_deref()).await?;
+    let Some(playlist) = found else {
+        return Err(StatusError::new(StatusCode::NOT_FOUND, "playlist not found").into());
+    };
+
+    let contents = match format {
+        Format::M3u | Format::M3u8 => to_m3u(&playlist).await?,
+        Format::Xspf => to_xspf(&playlist).await?,
+    };
+
+    info!(
+        "exported playlist '{}' as {}",
+        playlist.name,
+        format.extension()
+    );
+
+    // the id since it's always a safe file name
+    let stem = playlist.id();
+    Ok((
+        [
+            (header::CONTENT_TYPE, format.content_type().to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{stem}.{}\"", format.extension()),
+            ),
+        ],
+        contents,
+    ))
+}
+
+// absolute so the file still works once it's been downloaded somewhere else on this machine
+fn absolute(video: &Path) -> Result<PathBuf> {
+    Ok(env::current_dir()?.join(video))
+}
+
+// title & duration for #EXTINF/xspf, duration is none if it was never probed
+async fn describe(video: &Path) -> (String, Option<f64>) {
+    let title = video
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned();
+    let duration = metadata::get(video).await.probe.and_then(|p| p.duration);
+
+    (title, duration)
+}
+
+// the same for .m3u & .m3u8, anything that still reads .m3u as latin-1 is rare enough to not care
+async fn to_m3u(playlist: &Playlist) -> Result<String> {
+    let mut m3u = String::from("#EXTM3U\n");
+    // names can't have newlines in a one line directive
+    writeln!(
+        m3u,
+        "#PLAYLIST:{}",
+        playlist.name.replace(['\n', '\r'], " ")
+    )?;
+    for video in &playlist.videos {
+        let (title, duration) = describe(video).await;
+        // -1 is what the format uses for unknown
+        #[allow(clippy::cast_possible_truncation)]
+        let seconds = duration.map_or(-1, |d| d.round() as i64);
+
+        writeln!(m3u, "#EXTINF:{seconds},{title}")?;
+        writeln!(m3u, "{}", absolute(video)?.display())?;
+    }
+
+    Ok(m3u)
+}
+
+async fn to_xspf(playlist: &Playlist) -> Result<String> {
+    let mut xspf = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<playlist version=\"1\" xmlns=\"http://xspf.org/ns/0/\">\n",
+    );
+    writeln!(xspf, "  <title>{}</title>", escape(&playlist.name))?;
+    if let Some(description) = &playlist.description {
+        writeln!(xspf, "  <annotation>{}</annotation>", escape(description))?;
+    }
+    xspf.push_str("  <trackList>\n");
+
+    for video in &playlist.videos {
+        let (title, duration) = describe(video).await;
+        let location = absolute(video)?.to_string_lossy().into_owned();
+
+        xspf.push_str("    <track>\n");
+        writeln!(
+            xspf,
+            "      <location>file://{}</location>",
+            escape(utf8_percent_encode(&location, URI_ESCAPE).to_string())
+        )?;
+        writeln!(xspf, "      <title>{}</title>", escape(&title))?;
+        if let Some(duration) = duration {
+            // milliseconds here instead of seconds
+            #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+            let millis = (duration * 1000.0).round() as u64;
+            writeln!(xspf, "      <duration>{millis}</duration>")?;
+        }
+        xspf.push_str("    </track>\n");
+    }
+
+    xspf.push_str("  </trackList>\n</playlist>\n");
+    Ok(xspf)
+}
+
+#[derive(Deserialize)]
+struct ImportQuery {
+    playlist_name: String,
+    // guessed from the contents if none
+    format: Option<Format>,
+    // for when a playlist with that name already exists
+    #[serde(default)]
+    conflict: ConflictPolicy,
+}
+
+#[derive(Serialize)]
+struct ImportResponse {
+    playlist_id: String,
+    // can be different from what was asked for with the rename policy
+    playlist_name: String,
+    imported: usize,
+    unmatched: Vec<Unmatched>,
+}
+
+#[derive(Serialize)]
+struct Unmatched {
+    location: String,
+    title: Option<String>,
+}
+
+async fn import(
+    Query(ImportQuery {
+        playlist_name,
+        format,
+        conflict,
+    }): Query<ImportQuery>,
+    contents: Bytes,
+) -> WebResult<Json<ImportResponse>> {
+    let (contents, format) = decode(&contents, format)?;
+    let entries = match format {
+        Format::M3u | Format::M3u8 => parse_m3u(&contents),
+        Format::Xspf => parse_xspf(&contents)?,
+    };
+
+    let library = library::walk_all().await?.files;
+    let mut videos = vec![];
+    let mut unmatched = vec![];
+    for entry in entries {
+        match find_video(&entry.location, &library) {
+            Some(video) if !videos.contains(&video) => videos.push(video),
+            Some(_) => {}
+            None => unmatched.push(Unmatched {
+                location: entry.location,
+                title: entry.title,
+            }),
+        }
+    }
+
+    // writing it would just delete it
+    if videos.is_empty() {
+        let message = if unmatched.is_empty() {
+            "no entries found in the playlist".to_string()
+        } else {
+            format!(
+                "none of the {} entries matched anything in the library",
+                unmatched.len()
+            )
+        };
+
+        return Err(StatusError::new(StatusCode::UNPROCESSABLE_ENTITY, message).into());
+    }
+
+    let playlist_name = playlist::claim_name(&playlist_name, conflict, None).await?;
+    let imported = videos.len();
+    let playlist = Playlist::new(playlist_name.clone(), videos);
+    playlist::write_playlist(&playlist).await?;
+
+    info!(
+        "imported playlist '{playlist_name}' with {} videos, {} didn't match",
+        imported,
+        unmatched.len()
+    );
+
+    Ok(Json(ImportResponse {
+        playlist_id: playlist.id(),
+        playlist_name,
+        imported,
+        unmatched,
+    }))
+}
+
+// m3u8 & xspf are always utf-8, a plain .m3u is in whatever the machine that made it used
+// latin-1 is the usual one & any byte is valid latin-1, so it's the fallback when it isn't utf-8
+fn decode(contents: &[u8], format: Option<Format>) -> Result<(String, Format), StatusError> {
+    if let Ok(contents) = std::str::from_utf8(contents) {
+        let format = format.unwrap_or_else(|| Format::guess(contents));
+        return Ok((contents.to_string(), format));
+    }
+
+    let format =
+        format.unwrap_or_else(|| match Format::guess(&String::from_utf8_lossy(contents)) {
+            // what guess says for anything m3u, but utf-8 is what makes it an m3u8
+            Format::M3u8 => Format::M3u,
+            other => other,
+        });
+
+    if format != Format::M3u {
+        return Err(StatusError::new(
+            StatusCode::BAD_REQUEST,
+            format!(".{} playlists have to be utf-8", format.extension()),
+        ));
+    }
+
+    Ok((contents.iter().copied().map(char::from).collect(), format))
+}
+
+fn parse_m3u(contents: &str) -> Vec<Entry> {
+    let mut entries = vec![];
+    let mut title = None;
+
+    for line in contents.trim_start_matches('\u{feff}').lines() {
+        let line = line.trim();
+        if let Some(info) = line.strip_prefix("#EXTINF:") {
+            // '#EXTINF:123,artist - title', the title can have commas in it
+            title = info
+                .split_once(',')
+                .map(|(_, title)| title.trim().to_string())
+                .filter(|title| !title.is_empty());
+        } else if !line.is_empty() && !line.starts_with('#') {
+            entries.push(Entry {
+                location: line.to_string(),
+                title: title.take(),
+            });
+        }
+    }
+
+    entries
+}
+
+fn parse_xspf(contents: &str) -> Result<Vec<Entry>, StatusError> {
+    let invalid = |e: quick_xml::Error| {
+        StatusError::new(StatusCode::BAD_REQUEST, format!("invalid xspf: {e}"))
+    };
+
+    let mut reader = Reader::from_str(contents);
+    reader.config_mut().trim_text(true);
+
+    let mut entries = vec![];
+    // the element text is going into, only inside of a <track>
+    let mut current = None::<Vec<u8>>;
+    let mut track = None::<Entry>;
+
+    loop {
+        match reader.read_event().map_err(invalid)? {
+            Event::Start(e) => {
+                let name = e.local_name().as_ref().to_vec();
+                if name == b"track" {
+                    track = Some(Entry {
+                        location: String::new(),
+                        title: None,
+                    });
+                } else if track.is_some() {
+                    current = Some(name);
+                }
+            }
+            Event::Text(text) => {
+                let text = text.unescape().map_err(invalid)?;
+                set_field(track.as_mut(), current.as_deref(), &text);
+            }
+            Event::CData(data) => {
+                let text = String::from_utf8_lossy(&data);
+                set_field(track.as_mut(), current.as_deref(), &text);
+            }
+            Event::End(e) => {
+                if e.local_name().as_ref() == b"track" {
+                    entries.extend(track.take().filter(|t| !t.location.is_empty()));
+                }
+
+                current = None;
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    Ok(entries)
+}
+
+// a track can have more than one location, the first one wins
+fn set_field(track: Option<&mut Entry>, field: Option<&[u8]>, text: &str) {
+    let Some(track) = track else {
+        return;
+    };
+
+    match field {
+        Some(b"location") if track.location.is_empty() => text.clone_into(&mut track.location),
+        Some(b"title") if track.title.is_none() => track.title = Some(text.to_string()),
+        _ => {}
+    }
+}
+
+// tries the exact path first, then a library name, then just the file name
+// so a playlist made on another machine still matches as long as the files are named the same
+fn find_video(location: &str, library: &[PathBuf]) -> Option<PathBuf> {
+    let location = match location.strip_prefix("file://") {
+        // 'file://localhost/x' & 'file:///x' both mean '/x'
+        Some(rest) => percent_decode_str(rest.strip_prefix("localhost").unwrap_or(rest))
+            .decode_utf8_lossy()
+            .into_owned(),
+        // e.x. http streams, nothing to match them to
+        None if location.contains("://") => return None,
+        None => location.to_string(),
+    };
+
+    let in_library = |path: PathBuf| library.contains(&path).then_some(path);
+
+    let path = Path::new(&location);
+    let relative = env::current_dir()
+        .ok()
+        .and_then(|dir| path.strip_prefix(dir).ok().map(Path::to_path_buf));
+    let exact = relative
+        .or_else(|| Some(path.strip_prefix("../").unwrap_or(path).to_path_buf()))
+        .and_then(in_library);
+
+    exact
+        .or_else(|| library::resolve(&location).ok().and_then(in_library))
+        .or_else(|| by_file_name(&location, library))
+}
+
+fn by_file_name(location: &str, library: &[PathBuf]) -> Option<PathBuf> {
+    // windows paths too, e.x. 'C:\videos\rain.mp4'
+    let name = location.rsplit(['/', '\\']).next()?;
+    let mut matches = library
+        .iter()
+        .filter(|video| video.file_name().is_some_and(|n| n == name));
+
+    let first = matches.next()?;
+    // rather miss than guess between two videos with the same name
+    matches.next().is_none().then(|| first.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn locations(entries: &[Entry]) -> Vec<&str> {
+        entries.iter().map(|e| e.location.as_str()).collect()
+    }
+
+    #[test]
+    fn m3u() {
+        let entries = parse_m3u(
+            "\u{feff}#EXTM3U\n#PLAYLIST:lo fi\n#EXTINF:123,rain, at night\r\n/videos/rain.mp4\r\n\n# comment\nC:\\videos\\fire.mkv\n#EXTINF:-1,\nhttp://example.com/stream\n",
+        );
+
+        assert_eq!(
+            locations(&entries),
+            [
+                "/videos/rain.mp4",
+                "C:\\videos\\fire.mkv",
+                "http://example.com/stream"
+            ]
+        );
+        assert_eq!(entries[0].title.as_deref(), Some("rain, at night"));
+        assert_eq!(entries[1].title, None);
+        assert_eq!(entries[2].title, None);
+    }
+
+    #[test]
+    fn xspf() {
+        let location = "/videos/rain & fire 🌙.mp4";
+        let contents = format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<playlist version=\"1\" xmlns=\"http://xspf.org/ns/0/\">\n  <title>lo fi</title>\n  <trackList>\n    <track>\n      <location>file://{}</location>\n      <location>file:///other.mp4</location>\n      <title>{}</title>\n    </track>\n    <track><title>no location</title></track>\n    <track><location><![CDATA[/videos/fire.mkv]]></location></track>\n  </trackList>\n</playlist>\n",
+            escape(utf8_percent_encode(location, URI_ESCAPE).to_string()),
+            escape("<rain> & \"fire\""),
+        );
+
+        let entries = parse_xspf(&contents).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(
+            percent_decode_str(entries[0].location.strip_prefix("file://").unwrap())
+                .decode_utf8_lossy(),
+            location
+        );
+        assert_eq!(entries[0].title.as_deref(), Some("<rain> & \"fire\""));
+        assert_eq!(entries[1].location, "/videos/fire.mkv");
+    }
+
+    #[test]
+    fn decodes_latin1_m3u() {
+        let latin1 = b"#EXTINF:1,caf\xe9\n/videos/caf\xe9.mp4\n";
+
+        let (contents, format) = decode(latin1, None).unwrap();
+        assert_eq!(format, Format::M3u);
+        assert_eq!(locations(&parse_m3u(&contents)), ["/videos/café.mp4"]);
+
+        assert_eq!(decode("café".as_bytes(), None).unwrap().0, "café");
+        assert!(decode(latin1, Some(Format::M3u8)).is_err());
+        assert!(decode(b"<?xml version=\"1.0\"?>\xe9", None).is_err());
+    }
+
+    #[test]
+    fn invalid_xspf() {
+        assert!(parse_xspf("<playlist><trackList><track></playlist>").is_err());
+    }
+
+    #[test]
+    fn guesses_format() {
+        assert_eq!(
+            Format::guess("\u{feff}<?xml version=\"1.0\"?>"),
+            Format::Xspf
+        );
+        assert_eq!(Format::guess("  <playlist version=\"1\">"), Format::Xspf);
+        assert_eq!(Format::guess("#EXTM3U\n"), Format::M3u8);
+    }
+
+    #[test]
+    fn matches_by_file_name() {
+        let library = [
+            PathBuf::from("uploads/rain.mp4"),
+            PathBuf::from("uploads/a/fire.mkv"),
+            PathBuf::from("uploads/b/fire.mkv"),
+        ];
+
+        assert_eq!(
+            by_file_name("C:\\videos\\rain.mp4", &library),
+            Some(PathBuf::from("uploads/rain.mp4"))
+        );
+        assert_eq!(by_file_name("/elsewhere/fire.mkv", &library), None);
+        assert_eq!(by_file_name("snow.mp4", &library), None);
+    }
+}
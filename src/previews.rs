@@ -0,0 +1,191 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use axum::{extract::Query, http::StatusCode, Json};
+use serde::{Deserialize, Serialize};
+use tokio::{fs, process::Command};
+
+use crate::{
+    library, metadata,
+    uploads::unique_id,
+    web_util::{self, StatusError, WebResult},
+    PREVIEW_PATH, TMP_PATH,
+};
+
+// 5x2 grid of 160x90 frames, small enough to load on hover
+const COLUMNS: u32 = 5;
+const FRAMES: u32 = 10;
+const FRAME_WIDTH: u32 = 160;
+const FRAME_HEIGHT: u32 = 90;
+
+// what got generated, kept in metadata so the manifest always matches the sheet
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Preview {
+    pub columns: u32,
+    pub frame_width: u32,
+    pub frame_height: u32,
+    // seconds into the video for each frame, in order
+    pub timestamps: Vec<f64>,
+}
+
+// get path of uploads/folder/video.mp4
+// return previews/folder/video.mp4.jpg
+#[must_use]
+pub fn preview_path<P: AsRef<Path>>(video_path: P) -> PathBuf {
+    let video_path: &Path = video_path.as_ref();
+
+    let relative = library::mirror_relative(video_path);
+
+    // appended so rain.mkv & rain.mp4 get their own sheets
+    let mut preview_path = Path::new(PREVIEW_PATH).join(relative).into_os_string();
+    preview_path.push(".jpg");
+
+    preview_path.into()
+}
+
+// timeline sprite sheet, frames evenly spaced through the video
+pub async fn generate_preview(video: &Path, duration: f64) -> Result<Preview> {
+    // middle of each slice so the first frame isn't the usual black fade in
+    let timestamps = (0..FRAMES)
+        .map(|i| duration * (f64::from(i) + 0.5) / f64::from(FRAMES))
+        .collect::<Vec<_>>();
+
+    let frames_dir = Path::new(TMP_PATH).join(format!("preview-{}", unique_id()));
+    fs::create_dir_all(&frames_dir).await?;
+
+    let result = sprite_sheet(video, &timestamps, &frames_dir).await;
+    let _ = fs::remove_dir_all(&frames_dir).await;
+    result?;
+
+    Ok(Preview {
+        columns: COLUMNS,
+        frame_width: FRAME_WIDTH,
+        frame_height: FRAME_HEIGHT,
+        timestamps,
+    })
+}
+
+async fn sprite_sheet(video: &Path, timestamps: &[f64], frames_dir: &Path) -> Result<()> {
+    // seeking for every frame is way faster than decoding a 2 hour video start to finish
+    for (i, timestamp) in timestamps.iter().enumerate() {
+        // ffmpeg -ss 12.5 -i video.mp4 -vf scale..,pad.. -frames:v 1 frame_00.jpg
+        ffmpeg(
+            Command::new("ffmpeg")
+                .arg("-ss")
+                .arg(format!("{timestamp:.3}"))
+                .arg("-i")
+                .arg(video)
+                .arg("-vf")
+                .arg(format!(
+                    "scale={FRAME_WIDTH}:{FRAME_HEIGHT}:force_original_aspect_ratio=decrease,pad={FRAME_WIDTH}:{FRAME_HEIGHT}:(ow-iw)/2:(oh-ih)/2"
+                ))
+                .arg("-frames:v")
+                .arg("1")
+                .arg(frames_dir.join(format!("frame_{i:02}.jpg"))),
+        )
+        .await
+        .with_context(|| format!("failed to grab frame at {timestamp:.1}s"))?;
+    }
+
+    let preview_path = preview_path(video);
+    if let Some(parent) = preview_path.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+
+    let rows = FRAMES.div_ceil(COLUMNS);
+    // ffmpeg -i frame_%02d.jpg -vf tile=5x2 -frames:v 1 preview.jpg
+    ffmpeg(
+        Command::new("ffmpeg")
+            .arg("-i")
+            .arg(frames_dir.join("frame_%02d.jpg"))
+            .arg("-vf")
+            .arg(format!("tile={COLUMNS}x{rows}"))
+            .arg("-frames:v")
+            .arg("1")
+            .arg("-qscale:v")
+            .arg("5")
+            .arg("-update")
+            .arg("1")
+            .arg(&preview_path),
+    )
+    .await
+    .context("failed to tile preview frames")
+}
+
+async fn ffmpeg(command: &mut Command) -> Result<()> {
+    let output = command
+        .arg("-y")
+        .arg("-v")
+        .arg("error")
+        .kill_on_drop(true)
+        .output()
+        .await
+        .context("failed to run ffmpeg")?;
+
+    if !output.status.success() {
+        bail!(
+            "ffmpeg failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    Ok(())
+}
+
+#[derive(Deserialize)]
+pub struct ManifestQuery {
+    video_name: String,
+}
+
+#[derive(Serialize)]
+pub struct Manifest {
+    // e.x. '/previews/ambient/rain.jpg'
+    sprite: String,
+    columns: u32,
+    rows: u32,
+    frame_width: u32,
+    frame_height: u32,
+    frames: Vec<ManifestFrame>,
+}
+
+#[derive(Serialize)]
+struct ManifestFrame {
+    time: f64,
+    // top left of the frame in the sprite, in pixels
+    x: u32,
+    y: u32,
+}
+
+pub async fn manifest(
+    Query(ManifestQuery { video_name }): Query<ManifestQuery>,
+) -> WebResult<Json<Manifest>> {
+    let video = library::resolve(&video_name)?;
+    let sprite = preview_path(&video);
+
+    let preview = metadata::get(&video)
+        .await
+        .preview
+        .filter(|_| sprite.is_file())
+        .ok_or_else(|| StatusError::new(StatusCode::NOT_FOUND, "no preview for that video yet"))?;
+
+    let frames = (0..)
+        .zip(&preview.timestamps)
+        .map(|(i, &time)| ManifestFrame {
+            time,
+            x: (i % preview.columns) * preview.frame_width,
+            y: (i / preview.columns) * preview.frame_height,
+        })
+        .collect::<Vec<_>>();
+
+    #[allow(clippy::cast_possible_truncation)]
+    let rows = (frames.len() as u32).div_ceil(preview.columns);
+
+    Ok(Json(Manifest {
+        sprite: web_util::url_path(&sprite),
+        columns: preview.columns,
+        rows,
+        frame_width: preview.frame_width,
+        frame_height: preview.frame_height,
+        frames,
+    }))
+}
@@ -0,0 +1,86 @@
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use tokio::process::Command;
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct ProbeInfo {
+    // seconds, missing for some live-ish formats
+    pub duration: Option<f64>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub video_codec: Option<String>,
+    pub audio_codec: Option<String>,
+}
+
+// just the parts of ffprobe's json we care about
+#[derive(Deserialize)]
+struct FfprobeOutput {
+    #[serde(default)]
+    streams: Vec<FfprobeStream>,
+    format: Option<FfprobeFormat>,
+}
+
+#[derive(Deserialize)]
+struct FfprobeStream {
+    codec_type: Option<String>,
+    codec_name: Option<String>,
+    width: Option<u32>,
+    height: Option<u32>,
+}
+
+#[derive(Deserialize)]
+struct FfprobeFormat {
+    // ffprobe gives numbers as strings
+    duration: Option<String>,
+}
+
+pub async fn probe(path: &Path) -> Result<ProbeInfo> {
+    // ffprobe -v error -print_format json -show_format -show_streams video.mp4
+    let output = Command::new("ffprobe")
+        .arg("-v")
+        .arg("error")
+        .arg("-print_format")
+        .arg("json")
+        .arg("-show_format")
+        .arg("-show_streams")
+        .arg(path)
+        .output()
+        .await
+        .context("failed to run ffprobe")?;
+
+    if !output.status.success() {
+        bail!(
+            "ffprobe failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    let output = serde_json::from_slice::<FfprobeOutput>(&output.stdout)
+        .context("failed to parse ffprobe output")?;
+
+    let video = output
+        .streams
+        .iter()
+        .find(|s| s.codec_type.as_deref() == Some("video"));
+    let audio = output
+        .streams
+        .iter()
+        .find(|s| s.codec_type.as_deref() == Some("audio"));
+
+    if video.is_none() && audio.is_none() {
+        bail!("no audio or video streams");
+    }
+
+    Ok(ProbeInfo {
+        duration: output
+            .format
+            .and_then(|f| f.duration)
+            .and_then(|d| d.parse().ok()),
+        width: video.and_then(|v| v.width),
+        height: video.and_then(|v| v.height),
+        video_codec: video.and_then(|v| v.codec_name.clone()),
+        audio_codec: audio.and_then(|a| a.codec_name.clone()),
+    })
+}
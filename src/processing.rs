@@ -0,0 +1,176 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Result};
+
+use crate::{
+    config::config,
+    duplicates,
+    events::{self, Event, Stage},
+    jobs::{self, JobContext, JobTask},
+    library, loudness,
+    metadata::{self, VideoMeta},
+    previews::{generate_preview, preview_path},
+    probe, storage,
+    thumbnails::{generate_thumbnail, thumbnail_path, ThumbnailChoice},
+    transcode::{self, optimized_path, Optimized},
+    video_name,
+};
+
+// everything that happens to a video once it's in the library
+// progress goes out over /events so the ui knows when the thumbnail exists
+pub async fn process(path: &Path) {
+    jobs::enqueue(JobTask::Process {
+        video: path.to_path_buf(),
+    })
+    .await;
+}
+
+// catches up on anything that was added while the server was off or never finished
+pub async fn process_missing() -> Result<()> {
+    let mut tasks = vec![];
+    for video in library::walk_all().await?.files {
+        let meta = metadata::get(&video).await;
+        if !thumbnail_path(&video).exists()
+            || meta.probe.is_none()
+            || meta.sha256.is_none()
+            || needs_transcode(&video, meta.optimized)
+            || needs_loudness(&meta)
+            || needs_preview(&video, &meta)
+        {
+            tasks.push(JobTask::Process { video });
+        }
+    }
+
+    jobs::enqueue_all(tasks).await;
+    Ok(())
+}
+
+// nothing to measure if there's no audio
+fn needs_loudness(meta: &VideoMeta) -> bool {
+    meta.loudness.is_none()
+        && meta
+            .probe
+            .as_ref()
+            .is_some_and(|probe| probe.audio_codec.is_some())
+}
+
+// audio only stuff doesn't get one
+fn needs_preview(video: &Path, meta: &VideoMeta) -> bool {
+    (meta.preview.is_none() || !preview_path(video).is_file())
+        && meta
+            .probe
+            .as_ref()
+            .is_some_and(|probe| probe.video_codec.is_some() && probe.duration.is_some())
+}
+
+// also true for old videos once transcoding gets turned on
+fn needs_transcode(video: &Path, optimized: Option<Optimized>) -> bool {
+    if !config().transcode.enabled {
+        return false;
+    }
+
+    match optimized {
+        None => true,
+        Some(Optimized::Copy) => !optimized_path(video).is_file(),
+        Some(_) => false,
+    }
+}
+
+pub async fn run(path: &Path, ctx: &JobContext) -> Result<()> {
+    let video = video_name(path).unwrap_or_else(|| path.display().to_string());
+
+    match stages(path, &video, ctx).await {
+        Ok(path) => {
+            // transcoding can move the video, e.x. rain.mkv -> rain.mp4
+            let video = video_name(&path).unwrap_or(video);
+            stage(&video, Stage::Done, None);
+
+            // the generated files count towards the quota too
+            if let Err(e) = storage::enforce_retention(0).await {
+                ctx.log(format!("failed to free up space: {e:#}")).await;
+            }
+            Ok(())
+        }
+        Err(e) => {
+            if ctx.is_last_attempt() {
+                stage(&video, Stage::Failed, Some(e.to_string()));
+            }
+
+            Err(e)
+        }
+    }
+}
+
+// returns where the video ended up
+async fn stages(path: &Path, video: &str, ctx: &JobContext) -> Result<PathBuf> {
+    if !path.is_file() {
+        bail!("'{}' doesn't exist anymore", path.display());
+    }
+
+    // before transcoding so it's the hash of what was uploaded, see /duplicates
+    if metadata::get(path).await.sha256.is_none() {
+        let sha256 = duplicates::hash_file(path).await?;
+        ctx.log(format!("hashed: {sha256}")).await;
+        duplicates::record(path, sha256).await;
+    }
+
+    stage(video, Stage::Probe, None);
+    let info = probe::probe(path).await?;
+    ctx.log(format!(
+        "probed: {:?}s {:?}x{:?}",
+        info.duration, info.width, info.height
+    ))
+    .await;
+    metadata::update(path, |meta| meta.probe = Some(info.clone())).await?;
+
+    // thumbnail & preview first so the ui has something to show while the slow stuff runs
+    // they get moved along if transcoding replaces the original
+    let meta = metadata::get(path).await;
+    if !thumbnail_path(path).exists() {
+        // this takes forever for some reason
+        stage(video, Stage::Thumbnail, None);
+        let at = match meta.thumbnail {
+            Some(ThumbnailChoice::Timestamp { seconds }) => Some(seconds),
+            _ => None,
+        };
+        let thumbnail = generate_thumbnail(path, at).await?;
+        ctx.log(format!("generated thumbnail '{}'", thumbnail.display()))
+            .await;
+    }
+
+    if let Some(duration) = info.duration.filter(|_| needs_preview(path, &meta)) {
+        stage(video, Stage::Preview, None);
+        let preview = generate_preview(path, duration).await?;
+        ctx.log(format!(
+            "generated preview with {} frames",
+            preview.timestamps.len()
+        ))
+        .await;
+        metadata::update(path, |meta| meta.preview = Some(preview)).await?;
+    }
+
+    let mut path = path.to_path_buf();
+    if needs_transcode(&path, metadata::get(&path).await.optimized) {
+        stage(video, Stage::Transcode, None);
+        path = transcode::transcode(&path, &info, ctx).await?;
+    }
+
+    // after transcoding since that's the file that'll actually get played
+    if needs_loudness(&metadata::get(&path).await) {
+        stage(video, Stage::Loudness, None);
+        let loudness = loudness::measure(&transcode::playback_path(&path).await).await?;
+        ctx.log(format!("measured loudness: {loudness:.1} lufs"))
+            .await;
+        metadata::update(&path, |meta| meta.loudness = Some(loudness)).await?;
+    }
+
+    Ok(path)
+}
+
+fn stage(video: &str, stage: Stage, error: Option<String>) {
+    events::emit(Event::Processing {
+        video: video.to_string(),
+        stage,
+        error,
+    });
+}
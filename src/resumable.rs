@@ -0,0 +1,405 @@
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    sync::{Mutex, PoisonError},
+};
+
+use anyhow::Context;
+use axum::{
+    extract::{self, Query, Request},
+    http::{header, HeaderMap, StatusCode},
+    routing::get,
+    Json, Router,
+};
+use futures_util::TryStreamExt;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use simplelog::{info, warn};
+use tokio::{
+    fs::{self, OpenOptions},
+    task, time,
+};
+
+use crate::{
+    duplicates,
+    events::UploadProgress,
+    processing,
+    safe_path::SafePath,
+    storage,
+    uploads::{self, ConflictPolicy},
+    web_util::{stream_into, StatusError, WebResult},
+    AppState, HOUR, RESUMABLE_PATH,
+};
+
+// offset based resumable uploads, same idea as tus but a lot simpler
+//
+// POST   /uploads      {video_name, size, conflict} -> status with a new id
+// PATCH  /uploads/:id  raw chunk, the upload-offset header has to match the current offset
+// GET    /uploads/:id  status, the offset is where to resume from
+// GET    /uploads      every unfinished upload
+// DELETE /uploads/:id  give up on it
+//
+// everything lives in tmp/resumable/ so unfinished uploads survive restarts,
+// the size of the data file is the offset so a crash mid chunk just loses that chunk
+// ones that go a week without a chunk get deleted
+
+const UPLOAD_OFFSET: &str = "upload-offset";
+// an upload that hasn't gotten a chunk in this long is given up on, they can be gigabytes
+const MAX_IDLE_DAYS: u64 = 7;
+
+// ids that are receiving a chunk right now, two at once would interleave
+static RECEIVING: Lazy<Mutex<HashSet<String>>> = Lazy::new(Mutex::default);
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/uploads", get(list_uploads).post(create_upload))
+        .route(
+            "/uploads/:id",
+            get(upload_status).patch(upload_chunk).delete(cancel_upload),
+        )
+}
+
+#[derive(Serialize, Deserialize)]
+struct UploadMeta {
+    video_name: String,
+    size: u64,
+    conflict: ConflictPolicy,
+}
+
+#[derive(Serialize)]
+struct UploadStatus {
+    id: String,
+    video_name: String,
+    size: u64,
+    offset: u64,
+    complete: bool,
+    // where it ended up once complete
+    path: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct CreateUpload {
+    video_name: String,
+    size: u64,
+    #[serde(default)]
+    conflict: ConflictPolicy,
+}
+
+async fn create_upload(
+    Json(CreateUpload {
+        video_name,
+        size,
+        conflict,
+    }): Json<CreateUpload>,
+) -> WebResult<(StatusCode, Json<UploadStatus>)> {
+    let name = SafePath::new(&video_name)?;
+    // no point starting if it's going to get rejected at the end
+    uploads::resolve_conflict(&name, conflict)?;
+    storage::make_room(size).await?;
+
+    let id = uploads::unique_id();
+    let (meta_path, data_path) = upload_paths(&id)?;
+
+    fs::File::create(&data_path).await?;
+    let meta = UploadMeta {
+        video_name,
+        size,
+        conflict,
+    };
+    fs::write(&meta_path, serde_json::to_vec(&meta)?).await?;
+
+    info!("started resumable upload {id} for '{name}' ({size} bytes)");
+
+    Ok((StatusCode::CREATED, Json(status(id, meta, 0))))
+}
+
+async fn list_uploads() -> WebResult<Json<Vec<UploadStatus>>> {
+    let mut statuses = vec![];
+
+    let mut files = fs::read_dir(RESUMABLE_PATH).await?;
+    while let Some(entry) = files.next_entry().await? {
+        let path = entry.path();
+        if path.extension().is_none_or(|ext| ext != "json") {
+            continue;
+        }
+
+        let Some(id) = path.file_stem().and_then(|s| s.to_str()) else {
+            continue;
+        };
+
+        if let Ok((meta, offset)) = read_upload(id).await {
+            statuses.push(status(id.to_string(), meta, offset));
+        }
+    }
+
+    Ok(Json(statuses))
+}
+
+async fn upload_status(extract::Path(id): extract::Path<String>) -> WebResult<Json<UploadStatus>> {
+    let (meta, offset) = read_upload(&id).await?;
+    Ok(Json(status(id, meta, offset)))
+}
+
+#[derive(Deserialize)]
+struct ChunkQuery {
+    // lets a client pick another policy if the name got taken while uploading
+    conflict: Option<ConflictPolicy>,
+}
+
+async fn upload_chunk(
+    extract::Path(id): extract::Path<String>,
+    Query(ChunkQuery { conflict }): Query<ChunkQuery>,
+    headers: HeaderMap,
+    request: Request,
+) -> WebResult<Json<UploadStatus>> {
+    let _receiving = ReceivingGuard::acquire(&id)?;
+    let (mut meta, offset) = read_upload(&id).await?;
+
+    let client_offset = header_u64(&headers, UPLOAD_OFFSET).ok_or_else(|| {
+        StatusError::new(
+            StatusCode::BAD_REQUEST,
+            format!("missing or invalid {UPLOAD_OFFSET} header"),
+        )
+    })?;
+
+    if client_offset != offset {
+        return Err(StatusError::new(
+            StatusCode::CONFLICT,
+            format!("offset mismatch, upload is at {offset}"),
+        )
+        .into());
+    }
+
+    // the data file is only ever appended to up to size, but it's still state from disk
+    let Some(remaining) = meta.size.checked_sub(offset) else {
+        return Err(StatusError::new(
+            StatusCode::CONFLICT,
+            format!(
+                "upload has {offset} bytes, more than its size of {}",
+                meta.size
+            ),
+        )
+        .into());
+    };
+    let length = header_u64(&headers, header::CONTENT_LENGTH.as_str());
+    if length.is_some_and(|len| len > remaining) {
+        return Err(StatusError::new(
+            StatusCode::BAD_REQUEST,
+            format!("chunk is bigger than the {remaining} bytes left"),
+        )
+        .into());
+    }
+
+    // the disk could have filled up since the upload started, only this chunk has to fit though
+    // without a length it's cut off at whatever room there is & the client picks up from there
+    let room = storage::make_room(length.unwrap_or_default()).await?;
+
+    let progress = UploadProgress::starting_at(&meta.video_name, Some(meta.size), offset);
+    let stream = request
+        .into_body()
+        .into_data_stream()
+        .inspect_ok(|bytes| progress.advance(bytes.len()));
+
+    let (meta_path, data_path) = upload_paths(&id)?;
+    let file = OpenOptions::new().append(true).open(&data_path).await?;
+    let written = stream_into(file, stream, remaining.min(room)).await?;
+    let offset = offset + written;
+
+    if offset < meta.size {
+        progress.settle();
+        return Ok(Json(status(id, meta, offset)));
+    }
+
+    if let Some(conflict) = conflict {
+        meta.conflict = conflict;
+    }
+
+    let name = SafePath::new(&meta.video_name)?;
+    let checked = async {
+        uploads::validate(&data_path, &name).await?;
+
+        let sha256 = duplicates::hash_file(&data_path).await?;
+        let linked = duplicates::check(&sha256, &name, meta.conflict).await?;
+        anyhow::Ok((sha256, linked))
+    }
+    .await;
+
+    let (sha256, linked) = match checked {
+        Ok(checked) => checked,
+        Err(e) => {
+            // resuming wouldn't help, so the whole upload goes
+            progress.fail(&e);
+            let _ = fs::remove_file(&data_path).await;
+            let _ = fs::remove_file(&meta_path).await;
+            return Err(e.into());
+        }
+    };
+
+    let path = if let Some(path) = linked {
+        let _ = fs::remove_file(&data_path).await;
+        path
+    } else {
+        match uploads::move_into_library(&data_path, &name, meta.conflict).await {
+            Ok(path) => {
+                duplicates::record(&path, sha256).await;
+                path
+            }
+            Err(e) => {
+                progress.fail(&e);
+                return Err(e.into());
+            }
+        }
+    };
+    let _ = fs::remove_file(&meta_path).await;
+
+    info!(
+        "finished resumable upload {id}, saved to '{}'",
+        path.display()
+    );
+    progress.finish(&path.to_string_lossy());
+    processing::process(&path).await;
+
+    let mut status = status(id, meta, offset);
+    status.path = Some(path.to_string_lossy().into_owned());
+
+    Ok(Json(status))
+}
+
+async fn cancel_upload(extract::Path(id): extract::Path<String>) -> WebResult {
+    let _receiving = ReceivingGuard::acquire(&id)?;
+    read_upload(&id).await?;
+
+    let (meta_path, data_path) = upload_paths(&id)?;
+    let _ = fs::remove_file(data_path).await;
+    fs::remove_file(meta_path).await?;
+
+    info!("cancelled resumable upload {id}");
+
+    Ok(())
+}
+
+// checks every hour, same as the trash
+pub fn start_expiry() {
+    drop(task::spawn(async {
+        let mut interval = time::interval(HOUR);
+        loop {
+            interval.tick().await;
+            if let Err(e) = expire().await {
+                warn!("failed to expire abandoned uploads: {e:?}");
+            }
+        }
+    }));
+}
+
+async fn expire() -> anyhow::Result<()> {
+    let mut files = fs::read_dir(RESUMABLE_PATH).await?;
+    while let Some(entry) = files.next_entry().await? {
+        let path = entry.path();
+        if path.extension().is_none_or(|ext| ext != "json") {
+            continue;
+        }
+
+        let Some(id) = path.file_stem().and_then(|s| s.to_str()) else {
+            continue;
+        };
+
+        // the data file's mtime is when the last chunk came in
+        let Ok((meta_path, data_path)) = upload_paths(id) else {
+            continue;
+        };
+        let last_chunk = match fs::metadata(&data_path).await {
+            Ok(data) => data.modified()?,
+            Err(_) => entry.metadata().await?.modified()?,
+        };
+        if last_chunk.elapsed().unwrap_or_default().as_secs() < MAX_IDLE_DAYS * 24 * 60 * 60 {
+            continue;
+        }
+
+        let Ok(_receiving) = ReceivingGuard::acquire(id) else {
+            continue;
+        };
+
+        let _ = fs::remove_file(data_path).await;
+        fs::remove_file(meta_path).await?;
+        info!("expired resumable upload {id}, no chunks in {MAX_IDLE_DAYS} days");
+    }
+
+    Ok(())
+}
+
+fn status(id: String, meta: UploadMeta, offset: u64) -> UploadStatus {
+    UploadStatus {
+        id,
+        video_name: meta.video_name,
+        size: meta.size,
+        offset,
+        complete: offset >= meta.size,
+        path: None,
+    }
+}
+
+async fn read_upload(id: &str) -> anyhow::Result<(UploadMeta, u64)> {
+    let (meta_path, data_path) = upload_paths(id)?;
+
+    let Ok(meta) = fs::read(&meta_path).await else {
+        return Err(StatusError::new(StatusCode::NOT_FOUND, "upload not found").into());
+    };
+
+    let meta = serde_json::from_slice::<UploadMeta>(&meta).context("corrupt upload metadata")?;
+    let offset = fs::metadata(&data_path)
+        .await
+        .context("upload data is missing")?
+        .len();
+
+    Ok((meta, offset))
+}
+
+// (metadata, data)
+fn upload_paths(id: &str) -> Result<(PathBuf, PathBuf), StatusError> {
+    // ids come from unique_id so anything else is someone poking around
+    if id.is_empty() || !id.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(StatusError::new(
+            StatusCode::BAD_REQUEST,
+            "invalid upload id",
+        ));
+    }
+
+    let base = Path::new(RESUMABLE_PATH);
+    Ok((
+        base.join(format!("{id}.json")),
+        base.join(format!("{id}.data")),
+    ))
+}
+
+fn header_u64(headers: &HeaderMap, name: &str) -> Option<u64> {
+    headers.get(name)?.to_str().ok()?.trim().parse().ok()
+}
+
+struct ReceivingGuard(String);
+
+impl ReceivingGuard {
+    fn acquire(id: &str) -> Result<Self, StatusError> {
+        let inserted = RECEIVING
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(id.to_string());
+
+        if !inserted {
+            return Err(StatusError::new(
+                StatusCode::CONFLICT,
+                "upload is already receiving a chunk",
+            ));
+        }
+
+        Ok(Self(id.to_string()))
+    }
+}
+
+impl Drop for ReceivingGuard {
+    fn drop(&mut self) {
+        RECEIVING
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&self.0);
+    }
+}
@@ -0,0 +1,255 @@
+use std::{
+    fmt::{self, Display, Formatter},
+    path::Path,
+};
+
+use axum::http::StatusCode;
+use serde::{Deserialize, Serialize};
+
+use crate::web_util::StatusError;
+
+const MAX_COMPONENT_LEN: usize = 255;
+const MAX_PATH_LEN: usize = 1024;
+
+// stuff that either breaks paths or breaks other operating systems / vlc
+const INVALID_CHARS: &[char] = &['\\', ':', '*', '?', '"', '<', '>', '|'];
+
+// a user supplied path relative to one of the base folders, e.x. 'ambient/rain.mp4'
+// can't escape the folder it gets joined onto, so every name from a request goes through here
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct SafePath(String);
+
+impl SafePath {
+    pub fn new(raw: &str) -> Result<Self, StatusError> {
+        if raw.is_empty() {
+            return Err(invalid(raw, "is empty"));
+        }
+
+        if raw.len() > MAX_PATH_LEN {
+            return Err(invalid(raw, "is too long"));
+        }
+
+        if raw.starts_with('/') {
+            return Err(invalid(raw, "is absolute"));
+        }
+
+        for component in raw.split('/') {
+            validate_component(raw, component)?;
+        }
+
+        Ok(Self(raw.to_string()))
+    }
+
+    // same as new but can't contain any folders
+    pub fn file_name(raw: &str) -> Result<Self, StatusError> {
+        if raw.contains('/') {
+            return Err(invalid(raw, "can't contain a folder"));
+        }
+
+        Self::new(raw)
+    }
+
+    // for files that got into the library some other way, e.x. 'a: b?.mp4' -> 'a_ b_.mp4'
+    // none if there's nothing reasonable to turn it into
+    #[must_use]
+    pub fn sanitize(raw: &str) -> Option<Self> {
+        let sanitized = raw
+            .split('/')
+            .map(|component| {
+                let component = component
+                    .chars()
+                    .map(|c| {
+                        if c.is_control() || INVALID_CHARS.contains(&c) {
+                            '_'
+                        } else {
+                            c
+                        }
+                    })
+                    .collect::<String>();
+
+                match component.trim() {
+                    "" => "_".to_string(),
+                    trimmed if trimmed.starts_with('.') => format!("_{trimmed}"),
+                    trimmed => trimmed.to_string(),
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("/");
+
+        Self::new(&sanitized).ok()
+    }
+
+    #[must_use]
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+fn validate_component(raw: &str, component: &str) -> Result<(), StatusError> {
+    if component.is_empty() {
+        return Err(invalid(raw, "has an empty folder name"));
+    }
+
+    // covers '.', '..' and hidden files in one go
+    if component.starts_with('.') {
+        return Err(invalid(raw, "has a hidden or relative component"));
+    }
+
+    if component.len() > MAX_COMPONENT_LEN {
+        return Err(invalid(raw, "has a name that is too long"));
+    }
+
+    if component != component.trim() {
+        return Err(invalid(raw, "has leading or trailing whitespace"));
+    }
+
+    if component
+        .chars()
+        .any(|c| c.is_control() || INVALID_CHARS.contains(&c))
+    {
+        return Err(invalid(raw, "has invalid characters"));
+    }
+
+    Ok(())
+}
+
+// a SafePath only gets checked by its text, a symlink inside of root can still lead outside of it
+// goes by the deepest part of path that exists since the rest of it is about to be created
+pub fn ensure_inside(root: &Path, path: &Path) -> Result<(), StatusError> {
+    let (Ok(root), Some(real)) = (
+        root.canonicalize(),
+        path.ancestors().find_map(|p| p.canonicalize().ok()),
+    ) else {
+        return Ok(());
+    };
+
+    if real.starts_with(&root) {
+        return Ok(());
+    }
+
+    Err(StatusError::new(
+        StatusCode::BAD_REQUEST,
+        format!("'{}' points outside of the library", path.display()),
+    ))
+}
+
+fn invalid(raw: &str, reason: &str) -> StatusError {
+    StatusError::new(
+        StatusCode::BAD_REQUEST,
+        format!("invalid name '{}': {reason}", raw.escape_debug()),
+    )
+}
+
+// what serde goes through, so request bodies & job payloads get the same checks as new
+impl TryFrom<String> for SafePath {
+    type Error = StatusError;
+
+    fn try_from(raw: String) -> Result<Self, Self::Error> {
+        Self::new(&raw)
+    }
+}
+
+impl From<SafePath> for String {
+    fn from(path: SafePath) -> Self {
+        path.0
+    }
+}
+
+impl AsRef<Path> for SafePath {
+    fn as_ref(&self) -> &Path {
+        Path::new(&self.0)
+    }
+}
+
+impl Display for SafePath {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, os::unix, path::PathBuf, process};
+
+    use super::*;
+
+    #[test]
+    fn accepts_normal_names() {
+        for raw in ["rain.mp4", "ambient/rain.mp4", "a/b/c d.mkv", "夜 🌙.mp4"] {
+            assert_eq!(SafePath::new(raw).unwrap().as_str(), raw);
+        }
+    }
+
+    #[test]
+    fn rejects_traversal() {
+        for raw in [
+            "",
+            "..",
+            "../rain.mp4",
+            "ambient/../../rain.mp4",
+            "./rain.mp4",
+            "/etc/passwd",
+            "ambient//rain.mp4",
+            "ambient/",
+            ".hidden.mp4",
+        ] {
+            assert!(SafePath::new(raw).is_err(), "'{raw}' should be rejected");
+        }
+    }
+
+    #[test]
+    fn rejects_bad_characters() {
+        for raw in [
+            "a\\b.mp4",
+            "nas:rain.mp4",
+            "what?.mp4",
+            "a\nb.mp4",
+            " rain.mp4",
+            "rain.mp4 ",
+        ] {
+            assert!(SafePath::new(raw).is_err(), "'{raw}' should be rejected");
+        }
+
+        assert!(SafePath::new(&"a".repeat(MAX_COMPONENT_LEN + 1)).is_err());
+        assert!(SafePath::new(&["a"; MAX_PATH_LEN].join("/")).is_err());
+    }
+
+    #[test]
+    fn file_name_rejects_folders() {
+        assert!(SafePath::file_name("rain.mp4").is_ok());
+        assert!(SafePath::file_name("ambient/rain.mp4").is_err());
+    }
+
+    #[test]
+    fn sanitizes() {
+        let sanitize = |raw| SafePath::sanitize(raw).map(|p| p.0);
+        assert_eq!(sanitize("nas:rain.mp4").as_deref(), Some("nas_rain.mp4"));
+        assert_eq!(sanitize(" a? / b*.mp4").as_deref(), Some("a_/b_.mp4"));
+        assert_eq!(sanitize("../x.mp4").as_deref(), Some("_../x.mp4"));
+        assert_eq!(sanitize(&"a".repeat(MAX_PATH_LEN + 1)), None);
+    }
+
+    #[test]
+    fn deserializing_is_checked_too() {
+        assert!(serde_json::from_str::<SafePath>("\"ambient/rain.mp4\"").is_ok());
+        assert!(serde_json::from_str::<SafePath>("\"../rain.mp4\"").is_err());
+    }
+
+    #[test]
+    fn symlinks_out_of_the_root() {
+        let dir = std::env::temp_dir().join(format!("safe_path_test_{}", process::id()));
+        let (root, outside) = (dir.join("root"), dir.join("outside"));
+        fs::create_dir_all(root.join("folder")).unwrap();
+        fs::create_dir_all(&outside).unwrap();
+        unix::fs::symlink(&outside, root.join("link")).unwrap();
+
+        let inside = |path: PathBuf| ensure_inside(&root, &path).is_ok();
+        assert!(inside(root.join("folder/rain.mp4")));
+        assert!(inside(root.join("new/folder/rain.mp4")));
+        assert!(!inside(root.join("link")));
+        assert!(!inside(root.join("link/rain.mp4")));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,286 @@
+use std::{
+    collections::BTreeMap,
+    ffi::CString,
+    io,
+    mem::MaybeUninit,
+    path::{Path, PathBuf},
+    sync::{Mutex, PoisonError},
+    time::{Duration, Instant, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+use axum::{http::StatusCode, routing::get, Json, Router};
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use simplelog::{info, warn};
+use tokio::fs;
+
+use crate::{
+    config::config,
+    events::{self, Event, LibraryChange},
+    library, metadata, now, playlist, trash, video_name,
+    web_util::{StatusError, WebResult},
+    AppState, BROKEN_PATH, OPTIMIZED_PATH, PLAYLIST_PATH, PREVIEW_PATH, THUMB_PATH, TMP_PATH,
+    TRASH_PATH, VIDEO_PATH,
+};
+
+const MB: u64 = 1024 * 1024;
+// walking every folder for each resumable chunk is way too slow on a pi with a big library
+const TOTAL_MAX_AGE: Duration = Duration::from_secs(60);
+
+// when usage last added everything up, plus whatever make_room has handed out since
+static TOTAL: Lazy<Mutex<Option<(Instant, u64)>>> = Lazy::new(Mutex::default);
+
+// everything the server writes to, extra library roots aren't ours to fill up
+const FOLDERS: [&str; 8] = [
+    VIDEO_PATH,
+    THUMB_PATH,
+    OPTIMIZED_PATH,
+    PREVIEW_PATH,
+    PLAYLIST_PATH,
+    TRASH_PATH,
+    BROKEN_PATH,
+    TMP_PATH,
+];
+
+#[derive(Serialize)]
+pub struct Usage {
+    // bytes per folder, e.x. 'uploads' -> 123
+    folders: BTreeMap<String, u64>,
+    total: u64,
+    // none if there's no quota
+    quota: Option<u64>,
+    // on the disk the server runs from
+    free: u64,
+    min_free: u64,
+}
+
+pub async fn usage() -> Result<Usage> {
+    let mut folders = BTreeMap::new();
+    for folder in FOLDERS {
+        folders.insert(
+            folder.trim_end_matches('/').to_string(),
+            folder_size(Path::new(folder)).await?,
+        );
+    }
+
+    let total = folders.values().sum();
+    *TOTAL.lock().unwrap_or_else(PoisonError::into_inner) = Some((Instant::now(), total));
+
+    let storage = &config().storage;
+    Ok(Usage {
+        total,
+        folders,
+        quota: Some(storage.quota_mb * MB).filter(|quota| *quota > 0),
+        free: free_space()?,
+        min_free: storage.min_free_mb * MB,
+    })
+}
+
+// what usage adds up to, without walking every folder again if it was done recently
+async fn total() -> Result<u64> {
+    let cached = *TOTAL.lock().unwrap_or_else(PoisonError::into_inner);
+    match cached {
+        Some((at, total)) if at.elapsed() < TOTAL_MAX_AGE => Ok(total),
+        _ => Ok(usage().await?.total),
+    }
+}
+
+// anything freeing space calls this so the next check doesn't refuse based on the old total
+fn forget_total() {
+    *TOTAL.lock().unwrap_or_else(PoisonError::into_inner) = None;
+}
+
+// includes hidden files unlike library::walk, half written rsync files take up space too
+pub async fn folder_size(folder: &Path) -> io::Result<u64> {
+    let mut size = 0;
+    let mut pending = vec![folder.to_path_buf()];
+
+    while let Some(dir) = pending.pop() {
+        let mut read_dir = match fs::read_dir(&dir).await {
+            Ok(read_dir) => read_dir,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+
+        while let Some(entry) = read_dir.next_entry().await? {
+            let metadata = entry.metadata().await?;
+            if metadata.is_dir() {
+                pending.push(entry.path());
+            } else {
+                size += metadata.len();
+            }
+        }
+    }
+
+    Ok(size)
+}
+
+// bytes free on the disk the server runs from
+fn free_space() -> io::Result<u64> {
+    let path = CString::new(".")?;
+    let mut stat = MaybeUninit::<libc::statvfs>::uninit();
+
+    // safety: path is a valid c string & stat is only read once statvfs says it filled it in
+    let stat = unsafe {
+        if libc::statvfs(path.as_ptr(), stat.as_mut_ptr()) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        stat.assume_init()
+    };
+
+    // these are u32 on 32 bit pis
+    #[allow(clippy::useless_conversion)]
+    Ok(u64::from(stat.f_bavail) * u64::from(stat.f_frsize))
+}
+
+// how many bytes an upload can take up, runs retention first if it's on
+// only the disk's free space is checked without a quota, the folders don't need walking then
+pub async fn make_room(bytes: u64) -> Result<u64> {
+    if let Err(e) = enforce_retention(bytes).await {
+        warn!("failed to free up space: {e:?}");
+    }
+
+    let storage = &config().storage;
+    let mut room = free_space()?.saturating_sub(storage.min_free_mb * MB);
+    if storage.quota_mb > 0 {
+        room = room.min((storage.quota_mb * MB).saturating_sub(total().await?));
+    }
+
+    if room == 0 || bytes > room {
+        return Err(not_enough_space(bytes, room).into());
+    }
+
+    // counted as written already so back to back uploads can't both get the same room
+    if let Some((_, total)) = TOTAL
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .as_mut()
+    {
+        *total += bytes;
+    }
+
+    Ok(room)
+}
+
+fn not_enough_space(bytes: u64, room: u64) -> StatusError {
+    StatusError::new(
+        StatusCode::INSUFFICIENT_STORAGE,
+        format!(
+            "not enough space, needs {} mb but only {} mb are left",
+            bytes.div_ceil(MB),
+            room / MB
+        ),
+    )
+}
+
+// gets back under the quota with room for `needed` more bytes
+// the trash goes first since it's already been deleted once, then the least recently played videos
+pub async fn enforce_retention(needed: u64) -> Result<()> {
+    let storage = &config().storage;
+    if !storage.retention || storage.quota_mb == 0 {
+        return Ok(());
+    }
+
+    // wouldn't fit even if everything else got deleted
+    let quota = storage.quota_mb * MB;
+    if needed > quota {
+        return Ok(());
+    }
+
+    let total = total().await?;
+    let Some(mut over) = (total + needed).checked_sub(quota).filter(|over| *over > 0) else {
+        return Ok(());
+    };
+
+    info!("{} mb over the quota, freeing up space", over.div_ceil(MB));
+    over = over.saturating_sub(trash::free_up(over).await?);
+
+    for video in least_recently_played().await? {
+        if over == 0 {
+            break;
+        }
+
+        over = over.saturating_sub(retire(&video).await?);
+    }
+
+    forget_total();
+    if over > 0 {
+        warn!("still {} mb over the quota", over.div_ceil(MB));
+    }
+
+    Ok(())
+}
+
+// only uploads/, never played ones count from when they were added
+async fn least_recently_played() -> Result<Vec<PathBuf>> {
+    let mut videos = vec![];
+    for video in library::walk(Path::new(VIDEO_PATH)).await?.files {
+        let played = match metadata::get(&video).await.last_played {
+            Some(played) => played,
+            None => fs::metadata(&video)
+                .await?
+                .modified()?
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        };
+
+        videos.push((played, video));
+    }
+
+    videos.sort();
+    Ok(videos.into_iter().map(|(_, video)| video).collect())
+}
+
+// deleted for good, the trash would still take up the space
+// returns how many bytes it freed
+async fn retire(video: &Path) -> Result<u64> {
+    let mut freed = fs::metadata(video).await?.len();
+    for generated in library::generated_files(video) {
+        freed += fs::metadata(generated)
+            .await
+            .map(|m| m.len())
+            .unwrap_or_default();
+    }
+
+    fs::remove_file(video).await?;
+    library::remove_generated(video).await;
+    playlist::replace_video(video, None).await?;
+    metadata::remove(video).await?;
+
+    info!(
+        "deleted '{}' to stay under the quota ({} mb)",
+        video.display(),
+        freed.div_ceil(MB)
+    );
+
+    // the watcher would send this too but it can be turned off
+    if let Some(video) = video_name(video) {
+        events::emit(Event::Library {
+            video,
+            change: LibraryChange::Removed,
+        });
+    }
+
+    Ok(freed)
+}
+
+pub async fn played(videos: &[PathBuf]) {
+    let now = now();
+    if let Err(e) = metadata::update_all(videos, |meta| meta.last_played = Some(now)).await {
+        warn!(
+            "failed to save when {} videos were played: {e:?}",
+            videos.len()
+        );
+    }
+}
+
+pub fn router() -> Router<AppState> {
+    Router::new().route("/storage", get(storage))
+}
+
+async fn storage() -> WebResult<Json<Usage>> {
+    Ok(Json(usage().await?))
+}
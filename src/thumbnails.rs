@@ -1,33 +1,134 @@
-use std::path::{Path, PathBuf};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
 use anyhow::{bail, Context, Result};
-use simplelog::{error, info};
-use tokio::{fs, process::Command};
+use axum::{
+    body::Bytes,
+    extract::{DefaultBodyLimit, Query},
+    http::StatusCode,
+    routing::{post, put},
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use simplelog::info;
+use tokio::{fs, process::Command, time};
 
-use crate::{THUMB_PATH, VIDEO_PATH};
+use crate::{
+    config::config,
+    jobs::{self, JobContext, JobTask},
+    library, metadata, storage,
+    uploads::TempFile,
+    web_util::{StatusError, WebResult},
+    AppState, THUMB_PATH,
+};
 
-// get path of uploads/video.mp4
-// return thumbs/video.jpg
+// plenty for a jpeg/png, it gets shrunk down anyway
+const MAX_IMAGE_SIZE: usize = 20 * 1024 * 1024;
+const THUMBNAIL_FRAMES: u32 = 50;
+
+// set when someone picked the thumbnail themselves so regenerating doesn't throw it away
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ThumbnailChoice {
+    // frame at this many seconds in
+    Timestamp { seconds: f64 },
+    // uploaded image, can't be regenerated
+    Uploaded,
+}
+
+// get path of uploads/folder/video.mp4
+// return thumbs/folder/video.mp4.jpg
 #[must_use]
 pub fn thumbnail_path<P: AsRef<Path>>(video_path: P) -> PathBuf {
     let video_path: &Path = video_path.as_ref();
 
-    let mut thumb_path = PathBuf::from(THUMB_PATH);
-    thumb_path.push(video_path.file_name().unwrap_or_default());
-    thumb_path.set_extension("jpg");
+    // mirror the folder structure so same named videos in different folders don't collide
+    let relative = library::mirror_relative(video_path);
+
+    // appended so rain.mkv & rain.mp4 get their own thumbnails
+    let mut thumb_path = Path::new(THUMB_PATH).join(relative).into_os_string();
+    thumb_path.push(".jpg");
+
+    thumb_path.into()
+}
+
+// thumbnails used to replace the extension, so rain.mkv & rain.mp4 shared thumbs/rain.jpg
+// moved over if only one video could've had it, otherwise processing makes new ones
+// runs before the sweep so the old ones don't get thrown away as orphans first
+pub async fn migrate_names() -> Result<()> {
+    let mut claims = HashMap::<PathBuf, Vec<PathBuf>>::new();
+    for video in library::walk_all().await?.files {
+        let mut legacy = Path::new(THUMB_PATH).join(library::mirror_relative(&video));
+        legacy.set_extension("jpg");
+        claims.entry(legacy).or_default().push(video);
+    }
+
+    let mut moved = 0;
+    for (legacy, videos) in claims {
+        if !legacy.is_file() {
+            continue;
+        }
+
+        if let [video] = videos.as_slice() {
+            let thumbnail = thumbnail_path(video);
+            if !thumbnail.exists() {
+                fs::rename(&legacy, &thumbnail).await?;
+                moved += 1;
+            }
+            continue;
+        }
+
+        // can't tell whose it was, an uploaded image would otherwise never get replaced
+        for video in &videos {
+            if metadata::get(video).await.thumbnail == Some(ThumbnailChoice::Uploaded) {
+                metadata::update(video, |meta| meta.thumbnail = None).await?;
+            }
+        }
+        fs::remove_file(&legacy).await?;
+    }
 
-    thumb_path
+    if moved > 0 {
+        info!("renamed {moved} thumbnails to keep their video's extension");
+    }
+
+    Ok(())
 }
 
-pub async fn generate_thumbnail(video_path: &PathBuf) -> Result<PathBuf> {
+// picks a frame by itself unless given a timestamp
+pub async fn generate_thumbnail(video_path: &Path, at: Option<f64>) -> Result<PathBuf> {
     let thumbnail_path = thumbnail_path(video_path);
+    if let Some(parent) = thumbnail_path.parent() {
+        fs::create_dir_all(parent).await?;
+    }
 
-    // ffmpeg with this is so slow
-    let output = Command::new("ffmpeg")
-        .arg("-i")
-        .arg(video_path)
-        .arg("-vf")
-        .arg("scale=640:360,select='eq(pict_type,I)',thumbnail")
+    // jump part way in instead of decoding the whole thing, unknown durations just start at 0
+    let seek = match at {
+        Some(seconds) => Some(seconds),
+        None => metadata::get(video_path)
+            .await
+            .probe
+            .and_then(|p| p.duration)
+            .map(|duration| duration * config().thumbnail_position.clamp(0.0, 0.95)),
+    };
+
+    let mut command = Command::new("ffmpeg");
+    if let Some(seconds) = seek {
+        // seeking before the input only decodes from the nearest keyframe
+        command.arg("-ss").arg(format!("{seconds:.3}"));
+    }
+
+    command.arg("-i").arg(video_path).arg("-vf");
+    if at.is_some() {
+        command.arg("scale=640:360");
+    } else {
+        // picks the most representative of the next 50 frames, avoids landing on a black frame
+        command.arg(format!("scale=640:360,thumbnail={THUMBNAIL_FRAMES}"));
+    }
+
+    command
         .arg("-vframes")
         .arg("1")
         .arg("-qscale:v")
@@ -36,10 +137,15 @@ pub async fn generate_thumbnail(video_path: &PathBuf) -> Result<PathBuf> {
         .arg("none")
         .arg("-update")
         .arg("1")
+        .arg("-y")
         .arg(&thumbnail_path)
-        .output()
-        .await
-        .context("failed to generate thumbnail")?;
+        .kill_on_drop(true);
+
+    let limit = Duration::from_secs(config().thumbnail_timeout);
+    let Ok(output) = time::timeout(limit, command.output()).await else {
+        bail!("ffmpeg took longer than {}s", limit.as_secs());
+    };
+    let output = output.context("failed to generate thumbnail")?;
 
     if !output.status.success() {
         bail!("ffmpeg failed: {output:?}");
@@ -48,25 +154,174 @@ pub async fn generate_thumbnail(video_path: &PathBuf) -> Result<PathBuf> {
     Ok(thumbnail_path)
 }
 
-pub async fn generate_new_thumbs() -> Result<()> {
-    let mut files = fs::read_dir(VIDEO_PATH).await?;
-    while let Some(entry) = files.next_entry().await? {
-        let file_type = entry.file_type().await?;
-        if !file_type.is_file() {
-            continue;
-        }
+// runs as a job for bulk regeneration, keeps whatever frame was picked
+pub async fn regenerate(video: &Path, ctx: &JobContext) -> Result<()> {
+    if !video.is_file() {
+        bail!("'{}' doesn't exist anymore", video.display());
+    }
 
-        let thumbnail_path = thumbnail_path(entry.path());
-        if thumbnail_path.exists() {
-            continue;
+    let at = match metadata::get(video).await.thumbnail {
+        Some(ThumbnailChoice::Uploaded) => {
+            ctx.log("has an uploaded thumbnail, leaving it alone").await;
+            return Ok(());
         }
+        Some(ThumbnailChoice::Timestamp { seconds }) => Some(seconds),
+        None => None,
+    };
+
+    let thumbnail = generate_thumbnail(video, at).await?;
+    ctx.log(format!("regenerated thumbnail '{}'", thumbnail.display()))
+        .await;
+
+    Ok(())
+}
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route(
+            "/videos/thumbnail",
+            put(choose_frame)
+                .post(upload_thumbnail)
+                .layer(DefaultBodyLimit::max(MAX_IMAGE_SIZE)),
+        )
+        .route("/thumbnails/regenerate", post(regenerate_all))
+}
+
+#[derive(Deserialize)]
+struct ChooseFrame {
+    video_name: String,
+    // none goes back to letting ffmpeg pick
+    timestamp: Option<f64>,
+}
+
+async fn choose_frame(
+    Json(ChooseFrame {
+        video_name,
+        timestamp,
+    }): Json<ChooseFrame>,
+) -> WebResult {
+    let video = existing_video(&video_name)?;
 
-        info!("generating thumbnail for '{}'", entry.path().display());
-        match generate_thumbnail(&entry.path()).await {
-            Ok(_) => info!("generated thumbnail '{}'", thumbnail_path.display()),
-            Err(e) => error!("failed to generate thumbnail: {e:?}"),
+    if let Some(timestamp) = timestamp {
+        let duration = metadata::get(&video).await.probe.and_then(|p| p.duration);
+        if timestamp < 0.0 || duration.is_some_and(|d| timestamp >= d) {
+            return Err(StatusError::new(
+                StatusCode::BAD_REQUEST,
+                "timestamp is outside of the video",
+            )
+            .into());
         }
     }
 
+    info!(
+        "regenerating thumbnail for '{}' at {timestamp:?}",
+        video.display()
+    );
+    generate_thumbnail(&video, timestamp).await?;
+
+    let choice = timestamp.map(|seconds| ThumbnailChoice::Timestamp { seconds });
+    metadata::update(&video, |meta| meta.thumbnail = choice).await?;
+
     Ok(())
 }
+
+#[derive(Deserialize)]
+struct UploadThumbnail {
+    video_name: String,
+}
+
+// raw image as the body, anything ffmpeg can read
+async fn upload_thumbnail(
+    Query(UploadThumbnail { video_name }): Query<UploadThumbnail>,
+    // Bytes so the body limit actually applies, a raw Body just streams past it
+    image: Bytes,
+) -> WebResult {
+    let video = existing_video(&video_name)?;
+
+    storage::make_room(image.len() as u64).await?;
+    let temp = TempFile::new();
+    fs::write(temp.path(), image).await?;
+
+    let thumbnail_path = thumbnail_path(&video);
+    if let Some(parent) = thumbnail_path.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+
+    // re-encoding it checks it's actually an image & keeps it the same size as generated ones
+    // ffmpeg -i image -vf scale..,pad.. -frames:v 1 thumb.jpg
+    let output = Command::new("ffmpeg")
+        .arg("-v")
+        .arg("error")
+        .arg("-i")
+        .arg(temp.path())
+        .arg("-vf")
+        .arg("scale=640:360:force_original_aspect_ratio=decrease,pad=640:360:(ow-iw)/2:(oh-ih)/2")
+        .arg("-frames:v")
+        .arg("1")
+        .arg("-qscale:v")
+        .arg("5")
+        .arg("-update")
+        .arg("1")
+        .arg("-y")
+        .arg(&thumbnail_path)
+        .output()
+        .await
+        .context("failed to run ffmpeg")?;
+
+    if !output.status.success() {
+        return Err(StatusError::new(StatusCode::BAD_REQUEST, "couldn't read that image").into());
+    }
+
+    info!("saved uploaded thumbnail for '{}'", video.display());
+    metadata::update(&video, |meta| {
+        meta.thumbnail = Some(ThumbnailChoice::Uploaded);
+    })
+    .await?;
+
+    Ok(())
+}
+
+#[derive(Deserialize)]
+struct RegenerateAll {
+    // also throws away picked frames & uploaded images
+    #[serde(default)]
+    reset_custom: bool,
+}
+
+#[derive(Serialize)]
+struct RegenerateResponse {
+    queued: usize,
+}
+
+async fn regenerate_all(
+    Json(RegenerateAll { reset_custom }): Json<RegenerateAll>,
+) -> WebResult<(StatusCode, Json<RegenerateResponse>)> {
+    let videos = library::walk_all().await?.files;
+
+    if reset_custom {
+        metadata::update_all(&videos, |meta| meta.thumbnail = None).await?;
+    }
+
+    jobs::enqueue_all(videos.iter().map(|video| JobTask::Thumbnail {
+        video: video.clone(),
+    }))
+    .await;
+
+    info!("queued {} thumbnails to be regenerated", videos.len());
+
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(RegenerateResponse {
+            queued: videos.len(),
+        }),
+    ))
+}
+
+fn existing_video(video_name: &str) -> Result<PathBuf, StatusError> {
+    let video = library::resolve(video_name)?;
+    if !video.is_file() {
+        return Err(StatusError::new(StatusCode::NOT_FOUND, "video not found"));
+    }
+
+    Ok(video)
+}
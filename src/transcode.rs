@@ -0,0 +1,218 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use tokio::{fs, process::Command};
+
+use crate::{
+    config::{config, TranscodeConfig},
+    jobs::JobContext,
+    library, metadata, playlist,
+    probe::{self, ProbeInfo},
+    safe_path::SafePath,
+    uploads::{ConflictPolicy, TempFile},
+    video_name, OPTIMIZED_PATH,
+};
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Optimized {
+    // the original was already fine to play as is
+    AlreadyCompatible,
+    // transcoded copy lives in optimized/, the original is untouched
+    Copy,
+    // the original got swapped out for the transcoded version
+    Replaced,
+}
+
+// get path of uploads/folder/video.mkv
+// return optimized/folder/video.mkv.mp4
+#[must_use]
+pub fn optimized_path<P: AsRef<Path>>(video_path: P) -> PathBuf {
+    let video_path: &Path = video_path.as_ref();
+
+    let relative = library::mirror_relative(video_path);
+
+    // appended instead of replaced so rain.mkv & rain.mp4 don't share a copy
+    let mut optimized_path = Path::new(OPTIMIZED_PATH).join(relative).into_os_string();
+    optimized_path.push(".mp4");
+
+    optimized_path.into()
+}
+
+// what vlc should actually be given for a video
+pub async fn playback_path(video: &Path) -> PathBuf {
+    if metadata::get(video).await.optimized == Some(Optimized::Copy) {
+        let optimized = optimized_path(video);
+        if optimized.is_file() {
+            return optimized;
+        }
+    }
+
+    video.to_path_buf()
+}
+
+// returns where the video lives afterwards since replacing the original can change its extension
+pub async fn transcode(video: &Path, info: &ProbeInfo, ctx: &JobContext) -> Result<PathBuf> {
+    let target = &config().transcode;
+    if is_compatible(info, target) {
+        ctx.log("already playable as is, not transcoding").await;
+        metadata::update(video, |meta| {
+            meta.optimized = Some(Optimized::AlreadyCompatible);
+        })
+        .await?;
+
+        return Ok(video.to_path_buf());
+    }
+
+    ctx.log(format!(
+        "transcoding {:?} {:?}x{:?} to {}x{}",
+        info.video_codec, info.width, info.height, target.width, target.height
+    ))
+    .await;
+
+    // deleted if this fails or the job gets cancelled
+    let temp = TempFile::new();
+    ffmpeg(video, temp.path(), target, info.video_codec.is_some()).await?;
+
+    // originals outside of uploads/ are left alone, e.x. on a read only nas
+    let in_uploads = library::split_root(video).is_some_and(|(root, _)| root.name.is_empty());
+    if target.keep_original || !in_uploads {
+        temp.persist_to(&optimized_path(video)).await?;
+        metadata::update(video, |meta| {
+            meta.optimized = Some(Optimized::Copy);
+            // measured on the original, the copy is what gets played now
+            meta.loudness = None;
+        })
+        .await?;
+
+        return Ok(video.to_path_buf());
+    }
+
+    let new_path = replace_original(video, temp).await?;
+    // the codec, size etc were the original's, none just gets it probed again on the next startup
+    let probed = probe::probe(&new_path).await.ok();
+    metadata::update(&new_path, |meta| {
+        meta.probe = probed;
+        meta.optimized = Some(Optimized::Replaced);
+        meta.loudness = None;
+        meta.decode_check = None;
+    })
+    .await?;
+    ctx.log(format!("replaced original with '{}'", new_path.display()))
+        .await;
+
+    Ok(new_path)
+}
+
+fn is_compatible(info: &ProbeInfo, target: &TranscodeConfig) -> bool {
+    let video_ok = info.video_codec.as_deref().is_none_or(|c| c == "h264")
+        && info.width.is_none_or(|w| w <= target.width)
+        && info.height.is_none_or(|h| h <= target.height);
+    let audio_ok = info
+        .audio_codec
+        .as_deref()
+        .is_none_or(|c| c == "aac" || c == "mp3");
+
+    video_ok && audio_ok
+}
+
+async fn ffmpeg(input: &Path, output: &Path, target: &TranscodeConfig, video: bool) -> Result<()> {
+    let mut command = Command::new("ffmpeg");
+    command
+        .arg("-y")
+        .arg("-v")
+        .arg("error")
+        .arg("-i")
+        .arg(input);
+
+    if video {
+        video_args(&mut command, target);
+    } else {
+        // audio only, e.x. a flac that just needs its audio converted
+        // ffmpeg -i in -map 0:a:0 -c:a aac -f mp4 out
+        command.arg("-map").arg("0:a:0");
+    }
+
+    let output = command
+        .arg("-c:a")
+        .arg("aac")
+        .arg("-b:a")
+        .arg("160k")
+        .arg("-ac")
+        .arg("2")
+        .arg("-movflags")
+        .arg("+faststart")
+        .arg("-f")
+        .arg("mp4")
+        .arg(output)
+        .kill_on_drop(true)
+        .output()
+        .await
+        .context("failed to run ffmpeg")?;
+
+    if !output.status.success() {
+        bail!(
+            "ffmpeg failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    Ok(())
+}
+
+fn video_args(command: &mut Command, target: &TranscodeConfig) {
+    let (w, h) = (target.width, target.height);
+
+    // letterboxes into the target size so 16:9 stuff doesn't get stretched on a 4:3 tv
+    // ffmpeg -i in -vf scale..,pad.. -c:v libx264 -profile:v baseline -c:a aac -f mp4 out
+    command
+        .arg("-map")
+        .arg("0:v:0")
+        .arg("-map")
+        .arg("0:a:0?")
+        .arg("-vf")
+        .arg(format!(
+            "scale={w}:{h}:force_original_aspect_ratio=decrease:force_divisible_by=2,pad={w}:{h}:(ow-iw)/2:(oh-ih)/2,setsar=1"
+        ))
+        .arg("-c:v")
+        .arg("libx264")
+        .arg("-profile:v")
+        .arg("baseline")
+        .arg("-level")
+        .arg("3.0")
+        .arg("-preset")
+        .arg("veryfast")
+        .arg("-crf")
+        .arg(target.quality.to_string())
+        .arg("-pix_fmt")
+        .arg("yuv420p");
+}
+
+// rain.mkv -> rain.mp4, keeps playlists & metadata pointing at it
+async fn replace_original(video: &Path, transcoded: TempFile) -> Result<PathBuf> {
+    // same video so the thumbnail etc are all still fine
+    if video.extension().is_some_and(|ext| ext == "mp4") {
+        transcoded.persist_to(video).await?;
+        return Ok(video.to_path_buf());
+    }
+
+    let name = video_name(&video.with_extension("mp4")).context("video isn't in the library")?;
+    // don't clobber a different video that already has the .mp4 name
+    let new_path = transcoded
+        .persist(&SafePath::new(&name)?, ConflictPolicy::Rename)
+        .await?;
+
+    fs::remove_file(video).await?;
+    for (old, new) in library::generated_files(video)
+        .into_iter()
+        .zip(library::generated_files(&new_path))
+    {
+        let _ = fs::rename(old, new).await;
+    }
+
+    playlist::replace_video(video, Some(&new_path)).await?;
+    metadata::rename(video, &new_path).await?;
+
+    Ok(new_path)
+}
@@ -0,0 +1,355 @@
+use std::{
+    cmp::Reverse,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+use axum::{
+    extract::{self, Query},
+    http::StatusCode,
+    routing::{delete, get, post},
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use simplelog::{info, warn};
+use tokio::{fs, task, time};
+
+use crate::{
+    config::config,
+    library,
+    metadata::{self, VideoMeta},
+    now,
+    playlist::{self, Playlist},
+    safe_path::SafePath,
+    storage,
+    uploads::{self, unique_id, ConflictPolicy},
+    video_name,
+    vlc_manager::PlaybackMode,
+    watcher,
+    web_util::{StatusError, WebResult},
+    AppState, HOUR, TRASH_PATH,
+};
+
+// deleted videos go here instead of being gone for good
+//
+// trash/<id>/item.json     where it was, which playlists it was in & its metadata
+// trash/<id>/<file name>   the video itself
+// trash/<id>/thumbs etc    whatever was generated for it, named after the mirror folder
+
+const ITEM_FILE: &str = "item.json";
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct TrashItem {
+    pub id: String,
+    // where it was, e.x. 'uploads/ambient/rain.mp4'
+    pub video: PathBuf,
+    // unix seconds
+    pub deleted_at: u64,
+    pub playlists: Vec<Membership>,
+    pub metadata: VideoMeta,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Membership {
+    pub playlist: PathBuf,
+    pub position: usize,
+    // so a playlist that got removed for being empty comes back the same
+    // none for things trashed before these were kept
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub mode: Option<PlaybackMode>,
+}
+
+impl Membership {
+    fn playlist(&self) -> Playlist {
+        Playlist {
+            videos: vec![],
+            path: self.playlist.clone(),
+            name: self
+                .name
+                .clone()
+                .unwrap_or_else(|| playlist::legacy_name(&self.playlist)),
+            description: self.description.clone(),
+            mode: self.mode,
+        }
+    }
+}
+
+impl TrashItem {
+    fn dir(&self) -> PathBuf {
+        Path::new(TRASH_PATH).join(&self.id)
+    }
+
+    fn video_file(&self) -> PathBuf {
+        self.dir().join(self.video.file_name().unwrap_or_default())
+    }
+}
+
+// the mirror folder name without the slash, e.x. 'thumbs'
+fn generated_name(mirror: &str) -> &str {
+    mirror.trim_end_matches('/')
+}
+
+pub async fn trash(video: &Path) -> Result<TrashItem> {
+    let item = TrashItem {
+        id: unique_id(),
+        video: video.to_path_buf(),
+        deleted_at: now(),
+        playlists: memberships(video).await?,
+        metadata: metadata::get(video).await,
+    };
+
+    // other roots are usually another disk, so the video gets copied onto ours
+    if library::split_root(video).is_some_and(|(root, _)| !root.name.is_empty()) {
+        storage::make_room(fs::metadata(video).await?.len()).await?;
+    }
+
+    let dir = item.dir();
+    if let Err(e) = move_to_trash(video, &item).await {
+        // a half made entry would show up as something to restore
+        let _ = fs::remove_dir_all(&dir).await;
+        return Err(e);
+    }
+
+    for (mirror, generated) in library::MIRRORS.iter().zip(library::generated_files(video)) {
+        if generated.is_file() {
+            let _ = fs::rename(&generated, dir.join(generated_name(mirror))).await;
+        }
+    }
+
+    playlist::replace_video(video, None).await?;
+    metadata::remove(video).await?;
+
+    info!("moved '{}' to the trash as {}", video.display(), item.id);
+
+    Ok(item)
+}
+
+async fn move_to_trash(video: &Path, item: &TrashItem) -> Result<()> {
+    let dir = item.dir();
+    fs::create_dir_all(&dir).await?;
+    // written first so a crash halfway still leaves something restorable
+    fs::write(dir.join(ITEM_FILE), serde_json::to_vec_pretty(item)?).await?;
+
+    library::move_file(video, &item.video_file())
+        .await
+        .context("failed to move video to the trash")
+}
+
+// every playlist & position the video is at, so restoring puts it back in the same spot
+async fn memberships(video: &Path) -> Result<Vec<Membership>> {
+    let mut memberships = vec![];
+    for path in playlist::playlist_files().await? {
+        let playlist = playlist::read_playlist(&path).await?;
+        for (position, _) in playlist
+            .videos
+            .iter()
+            .enumerate()
+            .filter(|(_, v)| *v == video)
+        {
+            memberships.push(Membership {
+                playlist: path.clone(),
+                position,
+                name: Some(playlist.name.clone()),
+                description: playlist.description.clone(),
+                mode: playlist.mode,
+            });
+        }
+    }
+
+    Ok(memberships)
+}
+
+async fn restore(item: &TrashItem, conflict: ConflictPolicy) -> Result<PathBuf> {
+    let path = match library::split_root(&item.video) {
+        Some((root, _)) if root.name.is_empty() => {
+            let name = video_name(&item.video).context("trashed video wasn't in uploads/")?;
+            uploads::move_into_library(&item.video_file(), &SafePath::new(&name)?, conflict).await?
+        }
+        // other roots just go back where they were, renaming isn't worth it there
+        Some(_) => {
+            library::ensure_writable(&item.video)?;
+            if item.video.exists() && conflict != ConflictPolicy::Overwrite {
+                return Err(StatusError::new(
+                    StatusCode::CONFLICT,
+                    format!("'{}' already exists", item.video.display()),
+                )
+                .into());
+            }
+
+            if let Some(parent) = item.video.parent() {
+                fs::create_dir_all(parent).await?;
+            }
+
+            watcher::written(&item.video);
+            library::move_file(&item.video_file(), &item.video).await?;
+            item.video.clone()
+        }
+        None => bail!("'{}' isn't in a library root anymore", item.video.display()),
+    };
+
+    let dir = item.dir();
+    for (mirror, generated) in library::MIRRORS.iter().zip(library::generated_files(&path)) {
+        let trashed = dir.join(generated_name(mirror));
+        if !trashed.is_file() {
+            continue;
+        }
+
+        if let Some(parent) = generated.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+
+        let _ = fs::rename(&trashed, &generated).await;
+    }
+
+    let meta = item.metadata.clone();
+    metadata::update(&path, |m| *m = meta).await?;
+
+    for membership in &item.playlists {
+        playlist::insert_video(membership.playlist(), membership.position, &path).await?;
+    }
+
+    fs::remove_dir_all(&dir).await?;
+    info!("restored '{}' from the trash", path.display());
+
+    Ok(path)
+}
+
+async fn read_item(id: &str) -> Result<TrashItem> {
+    // ids come from unique_id so anything else is someone poking around
+    if id.is_empty() || !id.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(StatusError::new(StatusCode::BAD_REQUEST, "invalid trash id").into());
+    }
+
+    let Ok(data) = fs::read(Path::new(TRASH_PATH).join(id).join(ITEM_FILE)).await else {
+        return Err(StatusError::new(StatusCode::NOT_FOUND, "not in the trash").into());
+    };
+
+    serde_json::from_slice(&data).context("corrupt trash item")
+}
+
+async fn items() -> Result<Vec<TrashItem>> {
+    let mut items = vec![];
+    let mut read_dir = fs::read_dir(TRASH_PATH).await?;
+    while let Some(entry) = read_dir.next_entry().await? {
+        let id = entry.file_name().to_string_lossy().into_owned();
+        match read_item(&id).await {
+            Ok(item) => items.push(item),
+            Err(e) => warn!("skipping trash entry '{id}': {e}"),
+        }
+    }
+
+    // newest first
+    items.sort_by_key(|item| Reverse(item.deleted_at));
+    Ok(items)
+}
+
+// checks every hour, trash_days of 0 keeps things forever
+pub fn start_purge() {
+    drop(task::spawn(async {
+        let mut interval = time::interval(HOUR);
+        loop {
+            interval.tick().await;
+            if let Err(e) = purge().await {
+                warn!("failed to purge the trash: {e:?}");
+            }
+        }
+    }));
+}
+
+async fn purge() -> Result<()> {
+    let days = config().trash_days;
+    if days == 0 {
+        return Ok(());
+    }
+
+    let cutoff = now().saturating_sub(days * 24 * 60 * 60);
+    for item in items().await? {
+        if item.deleted_at < cutoff {
+            info!(
+                "purging '{}' from the trash after {days} days",
+                item.video.display()
+            );
+            fs::remove_dir_all(item.dir()).await?;
+        }
+    }
+
+    Ok(())
+}
+
+// oldest first until at least `bytes` are freed, for storage retention
+// returns how many actually were
+pub async fn free_up(bytes: u64) -> Result<u64> {
+    let mut freed = 0;
+    for item in items().await?.iter().rev() {
+        if freed >= bytes {
+            break;
+        }
+
+        freed += storage::folder_size(&item.dir()).await?;
+        fs::remove_dir_all(item.dir()).await?;
+        info!(
+            "purged '{}' from the trash to stay under the quota",
+            item.video.display()
+        );
+    }
+
+    Ok(freed)
+}
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/trash", get(list_trash).delete(empty_trash))
+        .route("/trash/:id", delete(delete_item))
+        .route("/trash/:id/restore", post(restore_item))
+}
+
+async fn list_trash() -> WebResult<Json<Vec<TrashItem>>> {
+    Ok(Json(items().await?))
+}
+
+#[derive(Deserialize)]
+struct RestoreQuery {
+    // in case something else took the name since
+    #[serde(default)]
+    conflict: ConflictPolicy,
+}
+
+#[derive(Serialize)]
+struct RestoreResponse {
+    // can be different from where it was with the rename policy
+    path: PathBuf,
+}
+
+async fn restore_item(
+    extract::Path(id): extract::Path<String>,
+    Query(RestoreQuery { conflict }): Query<RestoreQuery>,
+) -> WebResult<Json<RestoreResponse>> {
+    let item = read_item(&id).await?;
+    let path = restore(&item, conflict).await?;
+
+    Ok(Json(RestoreResponse { path }))
+}
+
+async fn delete_item(extract::Path(id): extract::Path<String>) -> WebResult {
+    let item = read_item(&id).await?;
+
+    info!(
+        "permanently deleting '{}' from the trash",
+        item.video.display()
+    );
+    fs::remove_dir_all(item.dir()).await.map_err(Into::into)
+}
+
+async fn empty_trash() -> WebResult {
+    let items = items().await?;
+    for item in &items {
+        fs::remove_dir_all(item.dir()).await?;
+    }
+
+    info!("emptied the trash, {} videos gone for good", items.len());
+    Ok(())
+}
@@ -0,0 +1,287 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result};
+use axum::{body::Bytes, http::StatusCode, BoxError};
+use futures_util::{Stream, TryStreamExt};
+use serde::{Deserialize, Serialize};
+use simplelog::{info, warn};
+use tokio::fs;
+
+use crate::{
+    duplicates,
+    events::UploadProgress,
+    library, metadata, probe,
+    safe_path::{self, SafePath},
+    storage, video_path, watcher,
+    web_util::{stream_to_file, StatusError},
+    TMP_PATH, VIDEO_PATH,
+};
+
+// what to do when an upload has the same name as an existing video
+#[derive(Serialize, Deserialize, Default, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ConflictPolicy {
+    #[default]
+    Reject,
+    // 'rain.mp4' -> 'rain (1).mp4'
+    Rename,
+    Overwrite,
+}
+
+impl FromStr for ConflictPolicy {
+    type Err = StatusError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "reject" => Ok(Self::Reject),
+            "rename" => Ok(Self::Rename),
+            "overwrite" => Ok(Self::Overwrite),
+            _ => Err(StatusError::new(
+                StatusCode::BAD_REQUEST,
+                format!("invalid conflict policy '{s}'"),
+            )),
+        }
+    }
+}
+
+// half written file in tmp/, deleted on drop unless it got moved into place
+// this is what cleans up after a client gives up mid upload since axum drops the handler
+pub struct TempFile {
+    path: PathBuf,
+    persisted: bool,
+}
+
+impl TempFile {
+    pub fn new() -> Self {
+        Self {
+            path: Path::new(TMP_PATH).join(format!("{}.part", unique_id())),
+            persisted: false,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // moves the finished file to uploads/, returns where it ended up
+    pub async fn persist(mut self, name: &SafePath, conflict: ConflictPolicy) -> Result<PathBuf> {
+        let path = move_into_library(&self.path, name, conflict).await?;
+        self.persisted = true;
+
+        Ok(path)
+    }
+
+    // for things that don't go in uploads/, e.x. transcoded copies
+    pub async fn persist_to(mut self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+
+        watcher::written(path);
+        fs::rename(&self.path, path)
+            .await
+            .with_context(|| format!("failed to move temp file to {}", path.display()))?;
+        self.persisted = true;
+
+        Ok(())
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if !self.persisted {
+            // blocking but it's just an unlink
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+// streams an upload into tmp/ then moves it into place once it's complete
+// total is just for progress events
+pub async fn receive<S, E>(
+    name: &SafePath,
+    conflict: ConflictPolicy,
+    total: Option<u64>,
+    stream: S,
+) -> Result<PathBuf>
+where
+    S: Stream<Item = Result<Bytes, E>> + Send,
+    E: Into<BoxError>,
+{
+    let progress = UploadProgress::new(name.as_str(), total);
+
+    let result = async {
+        // fail before receiving gigabytes of data we'd throw away
+        resolve_conflict(name, conflict)?;
+
+        let room = storage::make_room(total.unwrap_or_default()).await?;
+
+        let temp = TempFile::new();
+        let stream = stream.inspect_ok(|bytes| progress.advance(bytes.len()));
+        let written = stream_to_file(temp.path(), stream, room).await?;
+        info!("received {} bytes for '{name}'", written.bytes);
+
+        // temp gets deleted on the way out if it's not media or a linked duplicate
+        validate(temp.path(), name).await?;
+        if let Some(path) = duplicates::check(&written.sha256, name, conflict).await? {
+            return Ok(path);
+        }
+
+        let path = temp.persist(name, conflict).await?;
+        duplicates::record(&path, written.sha256).await;
+
+        Ok(path)
+    }
+    .await;
+
+    match &result {
+        Ok(path) => progress.finish(&path.to_string_lossy()),
+        Err(e) => progress.fail(e),
+    }
+
+    result
+}
+
+// probes a finished upload before it goes anywhere near the library
+// otherwise junk only shows up later as a missing thumbnail or vlc erroring on the tv
+pub async fn validate(path: &Path, name: &SafePath) -> Result<()> {
+    // ffprobe reads images & subtitles just fine, they'd never show up in the library though
+    if !library::is_media(Path::new(name.as_str())) {
+        return Err(StatusError::new(
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            format!("'{name}' doesn't have a video or audio file extension"),
+        )
+        .into());
+    }
+
+    match probe::probe(path).await {
+        Ok(_) => Ok(()),
+        // no ffprobe means nothing can be checked, better than refusing every upload
+        Err(e) if e.downcast_ref::<io::Error>().is_some() => {
+            warn!("couldn't check '{name}', accepting it anyway: {e:#}");
+            Ok(())
+        }
+        Err(e) => {
+            warn!("rejecting '{name}': {e:#}");
+            Err(StatusError::new(
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                format!("'{name}' isn't a playable media file: {e}"),
+            )
+            .into())
+        }
+    }
+}
+
+// moves a fully received file from tmp/ to uploads/, returns where it ended up
+pub async fn move_into_library(
+    from: &Path,
+    name: &SafePath,
+    conflict: ConflictPolicy,
+) -> Result<PathBuf> {
+    let name = resolve_conflict(name, conflict)?;
+    let path = video_path(&name);
+    safe_path::ensure_inside(Path::new(VIDEO_PATH), &path)?;
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+
+    let overwriting = path.exists();
+    watcher::written(&path);
+    fs::rename(from, &path)
+        .await
+        .with_context(|| format!("failed to move upload into {}", path.display()))?;
+
+    if overwriting {
+        // stale now, gets regenerated with the new video
+        library::remove_generated(&path).await;
+        let _ = metadata::update(&path, |meta| {
+            meta.optimized = None;
+            meta.loudness = None;
+            meta.preview = None;
+            meta.thumbnail = None;
+            meta.decode_check = None;
+            meta.sha256 = None;
+        })
+        .await;
+    }
+
+    Ok(path)
+}
+
+// picks the name the upload should actually be saved as
+pub fn resolve_conflict(name: &SafePath, conflict: ConflictPolicy) -> Result<SafePath> {
+    if !video_path(name).exists() {
+        return Ok(name.clone());
+    }
+
+    match conflict {
+        ConflictPolicy::Overwrite => Ok(name.clone()),
+        ConflictPolicy::Reject => Err(StatusError::new(
+            StatusCode::CONFLICT,
+            format!("a video named '{name}' already exists"),
+        )
+        .into()),
+        ConflictPolicy::Rename => free_name(name),
+    }
+}
+
+fn free_name(name: &SafePath) -> Result<SafePath> {
+    let path = Path::new(name.as_str());
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let ext = path
+        .extension()
+        .map(|e| format!(".{}", e.to_string_lossy()))
+        .unwrap_or_default();
+
+    let parent = path
+        .parent()
+        .map(|p| p.to_string_lossy())
+        .filter(|p| !p.is_empty())
+        .map(|p| format!("{p}/"))
+        .unwrap_or_default();
+
+    let mut n = 1;
+    loop {
+        let candidate = SafePath::new(&format!("{parent}{stem} ({n}){ext}"))?;
+        if !video_path(&candidate).exists() {
+            return Ok(candidate);
+        }
+
+        n += 1;
+    }
+}
+
+// anything left in tmp/ at startup is from an upload that died with the server
+pub async fn clean_temp() -> Result<()> {
+    let mut files = fs::read_dir(TMP_PATH).await?;
+    while let Some(entry) = files.next_entry().await? {
+        let path = entry.path();
+        if path.extension().is_none_or(|ext| ext != "part") {
+            continue;
+        }
+
+        warn!("removing aborted upload '{}'", path.display());
+        let _ = fs::remove_file(path).await;
+    }
+
+    Ok(())
+}
+
+// good enough to be unique on one machine without pulling in a uuid crate
+pub fn unique_id() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+
+    format!("{nanos:x}{:x}", COUNTER.fetch_add(1, Ordering::Relaxed))
+}
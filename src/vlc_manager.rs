@@ -1,12 +1,78 @@
 use anyhow::{Context, Result};
+use axum::http::StatusCode;
+use percent_encoding::percent_decode_str;
+use serde::{Deserialize, Serialize};
 use std::{
+    collections::HashMap,
+    io::{BufRead, BufReader, Write},
     path::PathBuf,
-    process::{Child, Command},
+    process::{Child, ChildStdin, ChildStdout, Command, Stdio},
+    str::FromStr,
     sync::mpsc::{self, Receiver, Sender},
     thread,
 };
 
-use crate::FLAGS;
+use tokio::runtime::Handle;
+
+use crate::{loudness, storage, web_util::StatusError, FLAGS};
+
+#[derive(Serialize, Deserialize, Default, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PlaybackMode {
+    InOrder,
+    // vlc picks, so the same video can come up again before everything else has played
+    #[default]
+    Shuffle,
+    // shuffled once on our side then played in order, every video plays before any repeats
+    ShuffleNoRepeat,
+    // loops the first video forever
+    RepeatOne,
+}
+
+impl PlaybackMode {
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::InOrder => "in_order",
+            Self::Shuffle => "shuffle",
+            Self::ShuffleNoRepeat => "shuffle_no_repeat",
+            Self::RepeatOne => "repeat_one",
+        }
+    }
+
+    // both set every time since vlc remembers random/repeat if they get toggled in its ui
+    const fn flags(self) -> [&'static str; 2] {
+        match self {
+            Self::Shuffle => ["--random", "--no-repeat"],
+            Self::RepeatOne => ["--no-random", "--repeat"],
+            Self::InOrder | Self::ShuffleNoRepeat => ["--no-random", "--no-repeat"],
+        }
+    }
+}
+
+impl FromStr for PlaybackMode {
+    type Err = StatusError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "in_order" => Ok(Self::InOrder),
+            "shuffle" => Ok(Self::Shuffle),
+            "shuffle_no_repeat" => Ok(Self::ShuffleNoRepeat),
+            "repeat_one" => Ok(Self::RepeatOne),
+            _ => Err(StatusError::new(
+                StatusCode::BAD_REQUEST,
+                format!("invalid playback mode '{s}'"),
+            )),
+        }
+    }
+}
+
+// a playlist item as vlc will report it starting
+pub struct PlaylistItem {
+    // the library path it's played for, e.x. 'uploads/rain.mp4' even when the optimized copy plays
+    pub video: PathBuf,
+    // loudness gain on top of the user's
+    pub gain: f32,
+}
 
 pub enum VlcMessage {
     StopVideo,
@@ -14,7 +80,9 @@ pub enum VlcMessage {
         file_path: PathBuf,
         gain: f32,
         visualizer: Option<String>,
-        shuffle: bool,
+        mode: PlaybackMode,
+        // by the absolute path vlc reports, followed over rc to set gains & record plays as each one starts
+        items: HashMap<PathBuf, PlaylistItem>,
     },
     PlayFromString {
         media: String,
@@ -25,12 +93,14 @@ pub enum VlcMessage {
 
 pub fn create_vlc_channel() -> Sender<VlcMessage> {
     let (send, rec) = mpsc::channel::<VlcMessage>();
-    let _ = thread::spawn(move || thread_worker(&rec));
+    // plays get saved from the vlc threads, which aren't part of the runtime
+    let runtime = Handle::current();
+    let _ = thread::spawn(move || thread_worker(&rec, &runtime));
 
     send
 }
 
-fn thread_worker(rec: &Receiver<VlcMessage>) {
+fn thread_worker(rec: &Receiver<VlcMessage>, runtime: &Handle) {
     let mut current_vlc_instance = None::<Child>;
 
     while let Ok(msg) = rec.recv() {
@@ -44,13 +114,16 @@ fn thread_worker(rec: &Receiver<VlcMessage>) {
                 file_path: path,
                 gain,
                 visualizer,
-                shuffle,
+                mode,
+                items,
             } => {
                 let vlc_instance = play_video(
                     path.to_str().unwrap_or_default(),
                     gain,
-                    &visualizer,
-                    shuffle,
+                    visualizer.as_ref(),
+                    mode,
+                    items,
+                    runtime,
                 )
                 .expect("failed to play video");
 
@@ -61,8 +134,15 @@ fn thread_worker(rec: &Receiver<VlcMessage>) {
                 gain,
                 visualizer,
             } => {
-                let vlc_instance = play_video(&media, gain, &visualizer, false)
-                    .expect("failed to play video from string");
+                let vlc_instance = play_video(
+                    &media,
+                    gain,
+                    visualizer.as_ref(),
+                    PlaybackMode::InOrder,
+                    HashMap::new(),
+                    runtime,
+                )
+                .expect("failed to play video from string");
 
                 current_vlc_instance = Some(vlc_instance);
             }
@@ -73,7 +153,14 @@ fn thread_worker(rec: &Receiver<VlcMessage>) {
     }
 }
 
-fn play_video(path: &str, gain: f32, visualizer: &Option<String>, shuffle: bool) -> Result<Child> {
+fn play_video(
+    path: &str,
+    gain: f32,
+    visualizer: Option<&String>,
+    mode: PlaybackMode,
+    items: HashMap<PathBuf, PlaylistItem>,
+    runtime: &Handle,
+) -> Result<Child> {
     let mut vlc_builder = Command::new("vlc");
 
     for flag in unsafe { FLAGS.get_unchecked() } {
@@ -88,12 +175,105 @@ fn play_video(path: &str, gain: f32, visualizer: &Option<String>, shuffle: bool)
             .arg(format!("--effect-list={vis}"));
     }
 
-    if shuffle {
-        vlc_builder.arg("--random");
+    vlc_builder.args(mode.flags());
+
+    if !items.is_empty() {
+        // rc on stdin/stdout, it says when an item starts so its volume can be set & its play recorded
+        vlc_builder
+            .arg("--extraintf=rc")
+            .arg("--rc-fake-tty")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped());
     }
 
-    vlc_builder
+    let mut child = vlc_builder
         .arg(path)
         .spawn()
-        .with_context(|| format!("Failed to launch VLC with video: {path:?}"))
+        .with_context(|| format!("Failed to launch VLC with video: {path:?}"))?;
+
+    if let (Some(stdin), Some(stdout)) = (child.stdin.take(), child.stdout.take()) {
+        // ends by itself once vlc gets killed & closes stdout
+        let runtime = runtime.clone();
+        let _ = thread::spawn(move || follow_items(stdout, stdin, &items, &runtime));
+    }
+
+    Ok(child)
+}
+
+// the user's gain is already on the command line, this is just the loudness part on top
+// plays are recorded here instead of when the playlist gets sent so retention only sees what really played
+fn follow_items(
+    stdout: ChildStdout,
+    mut stdin: ChildStdin,
+    items: &HashMap<PathBuf, PlaylistItem>,
+    runtime: &Handle,
+) {
+    for line in BufReader::new(stdout).lines() {
+        let Ok(line) = line else {
+            return;
+        };
+
+        let Some(item) = started_item(&line).and_then(|path| items.get(&path)) else {
+            continue;
+        };
+
+        let video = item.video.clone();
+        runtime.spawn(async move { storage::played(&[video]).await });
+
+        // set every time since vlc keeps the volume from the last item
+        if writeln!(stdin, "volume {}", item_volume(item.gain)).is_err() {
+            return;
+        }
+    }
+}
+
+// rc volume for a loudness factor, 256 is 100%
+fn item_volume(factor: f32) -> u32 {
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    let volume = (loudness::clamp_factor(factor) * 256.0).round() as u32;
+    volume
+}
+
+// 'status change: ( new input: file:///home/pi/crt/uploads/a%20b.mp4 )' -> /home/pi/crt/uploads/a b.mp4
+fn started_item(line: &str) -> Option<PathBuf> {
+    let (_, uri) = line.split_once("new input: ")?;
+    let uri = uri.trim_end().strip_suffix(')')?.trim_end();
+    let path = uri.strip_prefix("file://")?;
+
+    Some(PathBuf::from(
+        percent_decode_str(path).decode_utf8_lossy().into_owned(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_started_items() {
+        assert_eq!(
+            started_item("status change: ( new input: file:///home/pi/crt/uploads/a%20b%23.mp4 )"),
+            Some(PathBuf::from("/home/pi/crt/uploads/a b#.mp4"))
+        );
+        assert_eq!(
+            started_item("> status change: ( new input: file:///x/%E5%A4%9C.mp4 )"),
+            Some(PathBuf::from("/x/夜.mp4"))
+        );
+        assert_eq!(
+            started_item("status change: ( new input: https://example.com/a.mp4 )"),
+            None
+        );
+        assert_eq!(started_item("status change: ( audio volume: 256 )"), None);
+    }
+
+    #[test]
+    fn item_volumes() {
+        assert_eq!(item_volume(1.0), 256);
+        assert_eq!(item_volume(0.5), 128);
+        assert_eq!(item_volume(40.0), 512);
+        assert_eq!(item_volume(0.01), 64);
+        assert_eq!(item_volume(-3.0), 64);
+        assert_eq!(item_volume(f32::NAN), 256);
+        assert_eq!(item_volume(f32::INFINITY), 256);
+    }
 }
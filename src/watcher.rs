@@ -0,0 +1,201 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Mutex, PoisonError},
+    time::Duration,
+};
+
+use anyhow::Result;
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use once_cell::sync::Lazy;
+use simplelog::{info, warn};
+use tokio::{
+    sync::mpsc::{self, UnboundedReceiver},
+    task,
+    time::{self, Instant},
+};
+
+use crate::{
+    config::config,
+    events::{self, Event, LibraryChange},
+    library, metadata, playlist, processing, video_name, VIDEO_PATH,
+};
+
+// a file has to stop changing for this long before it's picked up
+// otherwise a big rsync/cp would get processed halfway through
+const SETTLE_TIME: Duration = Duration::from_secs(3);
+// long enough for the event to settle, short enough that a copy over it right after still counts
+const OWN_WRITE_TIME: Duration = Duration::from_secs(30);
+
+// videos the server just put into a root itself, they already have a process job of their own
+static OWN_WRITES: Lazy<Mutex<HashMap<PathBuf, Instant>>> = Lazy::new(Mutex::default);
+
+// picks up files that get copied straight into uploads/ without going through the server
+// our own renames/deletes show up here too, everything it does is safe to repeat
+// new videos from uploads, downloads etc are skipped though, see written
+pub fn start() -> Result<()> {
+    let (tx, rx) = mpsc::unbounded_channel::<PathBuf>();
+
+    let mut watcher = RecommendedWatcher::new(
+        move |event: notify::Result<notify::Event>| match event {
+            Ok(event) if !matches!(event.kind, EventKind::Access(_)) => {
+                for path in event.paths {
+                    let _ = tx.send(path);
+                }
+            }
+            Ok(_) => {}
+            Err(e) => warn!("library watcher error: {e}"),
+        },
+        notify::Config::default(),
+    )?;
+
+    watcher.watch(Path::new(VIDEO_PATH), RecursiveMode::Recursive)?;
+    info!("watching '{VIDEO_PATH}' for changes");
+
+    // network mounts usually don't send events for changes made on the other end, restart for those
+    for root in &config().roots {
+        match watcher.watch(&root.path, RecursiveMode::Recursive) {
+            Ok(()) => info!("watching '{}' for changes", root.path.display()),
+            Err(e) => warn!("can't watch library root '{}': {e}", root.name),
+        }
+    }
+
+    // the watcher stops once it's dropped so it lives in the task
+    drop(task::spawn(async move {
+        let _watcher = watcher;
+        settle(rx).await;
+    }));
+
+    Ok(())
+}
+
+async fn settle(mut rx: UnboundedReceiver<PathBuf>) {
+    let mut pending = HashMap::<PathBuf, Instant>::new();
+    let mut tick = time::interval(Duration::from_secs(1));
+
+    loop {
+        tokio::select! {
+            path = rx.recv() => {
+                let Some(path) = path else {
+                    return;
+                };
+
+                if let Some(path) = relevant(&path) {
+                    pending.insert(path, Instant::now());
+                }
+            }
+            _ = tick.tick() => {
+                let settled = pending
+                    .iter()
+                    .filter(|(_, changed)| changed.elapsed() >= SETTLE_TIME)
+                    .map(|(path, _)| path.clone())
+                    .collect::<Vec<_>>();
+
+                for path in &settled {
+                    pending.remove(path);
+                }
+
+                // a folder already covers everything inside of it
+                for path in settled
+                    .iter()
+                    .filter(|p| !settled.iter().any(|other| *p != other && p.starts_with(other)))
+                {
+                    if let Err(e) = handle(path, &pending).await {
+                        warn!("failed to handle change to '{}': {e:?}", path.display());
+                    }
+                }
+            }
+        }
+    }
+}
+
+// notify gives absolute paths, turns them back into uploads/... & skips hidden stuff
+// (rsync writes to '.name.XXXXXX' then renames it into place)
+fn relevant(path: &Path) -> Option<PathBuf> {
+    let cwd = std::env::current_dir().ok()?;
+    let (root, relative) = library::roots().find_map(|root| {
+        // joining an absolute path just gives the absolute path back
+        let relative = path.strip_prefix(cwd.join(&root.path)).ok()?;
+        Some((root, relative))
+    })?;
+
+    if relative.as_os_str().is_empty()
+        || relative
+            .components()
+            .any(|c| c.as_os_str().to_string_lossy().starts_with('.'))
+    {
+        return None;
+    }
+
+    Some(root.path.join(relative))
+}
+
+// pending is what's still changing, it gets handled on its own once it settles
+async fn handle(path: &Path, pending: &HashMap<PathBuf, Instant>) -> Result<()> {
+    if path.is_file() {
+        // e.x. subtitles or a .nfo copied in next to a video
+        if library::is_media(path) {
+            added(path).await;
+        }
+    } else if path.is_dir() {
+        // moving a folder in only gives an event for the folder itself
+        for video in library::walk(path).await?.files {
+            if !pending.contains_key(&video) {
+                added(&video).await;
+            }
+        }
+    } else {
+        removed(path).await?;
+    }
+
+    Ok(())
+}
+
+// call before writing a video into a root so the watcher doesn't process it a second time
+pub fn written(video: &Path) {
+    let mut writes = OWN_WRITES.lock().unwrap_or_else(PoisonError::into_inner);
+    writes.retain(|_, at| at.elapsed() < OWN_WRITE_TIME);
+    writes.insert(video.to_path_buf(), Instant::now());
+}
+
+fn was_written(video: &Path) -> bool {
+    let mut writes = OWN_WRITES.lock().unwrap_or_else(PoisonError::into_inner);
+    writes
+        .remove(video)
+        .is_some_and(|at| at.elapsed() < OWN_WRITE_TIME)
+}
+
+async fn added(video: &Path) {
+    if was_written(video) {
+        return;
+    }
+
+    info!("picked up '{}'", video.display());
+
+    // already processed ones just get probed again, the rest of the stages are skipped
+    processing::process(video).await;
+    emit(video, LibraryChange::Added);
+}
+
+// could've been a single video or a whole folder, cleaning up both ways is harmless
+async fn removed(path: &Path) -> Result<()> {
+    library::remove_generated(path).await;
+    library::remove_generated_folder(path).await;
+
+    let changed = playlist::replace_folder(path, None).await?;
+    metadata::remove_folder(path).await?;
+
+    info!(
+        "'{}' was removed, updated {changed} playlists",
+        path.display()
+    );
+    emit(path, LibraryChange::Removed);
+
+    Ok(())
+}
+
+fn emit(path: &Path, change: LibraryChange) {
+    if let Some(video) = video_name(path) {
+        events::emit(Event::Library { video, change });
+    }
+}
@@ -1,25 +1,39 @@
-use std::path::Path;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
 
 use anyhow::{anyhow, Context};
 use axum::{
-    extract::{Query, Request, State},
-    routing::{get, patch, put},
+    extract::{self, multipart::Field, DefaultBodyLimit, Multipart, Query, Request, State},
+    http::{header, StatusCode},
+    routing::{get, patch, post, put},
     Json, Router,
 };
-use futures_util::TryStreamExt;
 use serde::{Deserialize, Serialize};
 use simplelog::{info, warn};
-use tokio::{fs, process::Command, task};
-use tokio_stream::{wrappers::ReadDirStream, StreamExt};
+use tokio::{fs, process::Command};
 
 use crate::{
+    downloads::{self, Download},
+    duplicates, events,
+    jobs::{self, JobTask},
+    library, loudness, maintenance,
     media_keys::MediaKeyMessage,
+    metadata,
     playlist::{self, Playlist},
-    thumbnails::{generate_thumbnail, thumbnail_path},
-    video_path,
-    vlc_manager::VlcMessage,
-    web_util::{stream_to_file, AppError},
-    AppState, VIDEO_PATH,
+    playlist_formats, previews, processing, resumable,
+    safe_path::SafePath,
+    storage,
+    thumbnails::{self, thumbnail_path},
+    transcode::{self, Optimized},
+    trash::{self, TrashItem},
+    uploads::{self, ConflictPolicy},
+    video_name,
+    vlc_manager::{PlaybackMode, VlcMessage},
+    web_util::{self, StatusError, WebResult},
+    AppState,
 };
 
 pub fn manager_router() -> Router<AppState> {
@@ -30,14 +44,45 @@ pub fn manager_router() -> Router<AppState> {
             get(videos)
                 .post(file_upload)
                 .delete(delete_video)
-                .put(switch_video),
+                .put(switch_video)
+                .patch(rename_video),
         )
         .route(
             "/playlists",
             get(playlists).post(save_playlist).put(play_playlist),
         )
-        .route("/custom-media", put(play_media))
+        .route(
+            "/playlists/:id",
+            patch(update_playlist).delete(delete_playlist),
+        )
+        .route("/playlists/:id/entries", post(insert_entry))
+        .route(
+            "/playlists/:id/entries/:index",
+            patch(move_entry).delete(remove_entry),
+        )
+        .route(
+            "/folders",
+            get(folders)
+                .post(create_folder)
+                .patch(move_folder)
+                .delete(delete_folder),
+        )
+        .route("/videos/preview", get(previews::manifest))
+        .route(
+            "/videos/multipart",
+            post(multipart_upload).layer(DefaultBodyLimit::disable()),
+        )
+        .route("/events", get(events::event_stream))
+        .route("/custom-media", put(play_media).post(save_media))
         .route("/media-control", patch(media_control))
+        .merge(resumable::router())
+        .merge(jobs::router())
+        .merge(thumbnails::router())
+        .merge(maintenance::router())
+        .merge(trash::router())
+        .merge(duplicates::router())
+        .merge(storage::router())
+        .merge(playlist_formats::router())
 }
 
 #[derive(Deserialize)]
@@ -45,23 +90,36 @@ struct VideoName {
     video_name: String,
 }
 
-type WebResult<T = ()> = Result<T, AppError>;
+#[derive(Deserialize)]
+struct UploadQuery {
+    video_name: String,
+    #[serde(default)]
+    conflict: ConflictPolicy,
+}
 
 async fn file_upload(
-    Query(VideoName { video_name }): Query<VideoName>,
+    Query(UploadQuery {
+        video_name,
+        conflict,
+    }): Query<UploadQuery>,
     request: Request,
 ) -> WebResult<String> {
-    let path = stream_to_file(&video_name, request.into_body().into_data_stream()).await?;
+    let video_name = SafePath::new(&video_name)?;
+    let total = request
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|len| len.to_str().ok()?.parse().ok());
+
+    let path = uploads::receive(
+        &video_name,
+        conflict,
+        total,
+        request.into_body().into_data_stream(),
+    )
+    .await?;
     info!("uploaded file to '{}'", path.display());
 
-    let path_for_task = path.clone();
-    // this takes forever for some reason
-    let _ = task::spawn(async move {
-        match generate_thumbnail(&path_for_task).await {
-            Ok(t) => info!("generated thumbnail at '{}'", t.display()),
-            Err(e) => warn!("failed to generate thumbnail: {e}"),
-        }
-    });
+    processing::process(&path).await;
 
     let path_string = path
         .to_str()
@@ -71,6 +129,129 @@ async fn file_upload(
     Ok(path_string)
 }
 
+#[derive(Serialize, Default)]
+struct MultipartResult {
+    // original file name from the form
+    file_name: Option<String>,
+    // what it got saved as
+    path: Option<String>,
+    error: Option<String>,
+}
+
+// form fields, in order:
+// conflict - optional, applies to every file after it
+// name, tags, playlist - optional, only apply to the next file
+// file - the actual file, saved under its own file name unless there was a name field
+async fn multipart_upload(mut multipart: Multipart) -> WebResult<Json<Vec<MultipartResult>>> {
+    let mut results = vec![];
+
+    // fields come in as a stream so files before a bad field are already saved by the time it shows up
+    // those still get reported, the error goes on the end instead of replacing them
+    if let Err(e) = read_multipart(&mut multipart, &mut results).await {
+        if results.is_empty() {
+            return Err(e.into());
+        }
+
+        warn!(
+            "multipart upload stopped after {} files: {e:?}",
+            results.len()
+        );
+        results.push(MultipartResult {
+            error: Some(e.to_string()),
+            ..Default::default()
+        });
+    }
+
+    info!("multipart upload finished with {} files", results.len());
+
+    Ok(Json(results))
+}
+
+async fn read_multipart(
+    multipart: &mut Multipart,
+    results: &mut Vec<MultipartResult>,
+) -> anyhow::Result<()> {
+    let mut conflict = ConflictPolicy::default();
+    let (mut name, mut tags, mut playlist) = (None::<String>, vec![], None::<String>);
+
+    while let Some(field) = multipart.next_field().await? {
+        match field.name().unwrap_or_default() {
+            "conflict" => conflict = field.text().await?.parse()?,
+            "name" => name = Some(field.text().await?),
+            "tags" => tags = parse_tags(&field.text().await?),
+            "playlist" => playlist = Some(field.text().await?),
+            "file" => {
+                let file_name = field.file_name().map(ToString::to_string);
+                let name = name
+                    .take()
+                    .or_else(|| file_name.clone())
+                    .unwrap_or_default();
+                let (tags, playlist) = (std::mem::take(&mut tags), playlist.take());
+
+                let mut result = MultipartResult {
+                    file_name,
+                    ..Default::default()
+                };
+
+                match save_multipart_file(&name, conflict, tags, playlist, field).await {
+                    Ok(path) => result.path = Some(path.to_string_lossy().into_owned()),
+                    Err(e) => {
+                        warn!("failed to save '{name}' from multipart upload: {e:?}");
+                        result.error = Some(e.to_string());
+                    }
+                }
+
+                results.push(result);
+            }
+            other => warn!("ignoring unknown multipart field '{other}'"),
+        }
+    }
+
+    Ok(())
+}
+
+async fn save_multipart_file(
+    name: &str,
+    conflict: ConflictPolicy,
+    tags: Vec<String>,
+    playlist: Option<String>,
+    field: Field<'_>,
+) -> anyhow::Result<PathBuf> {
+    let name = SafePath::new(name)?;
+    // check before writing anything so a bad playlist name doesn't leave a stray video
+    if let Some(playlist) = &playlist {
+        playlist::validate_name(playlist)?;
+    }
+
+    let path = uploads::receive(&name, conflict, None, field).await?;
+    info!("uploaded file to '{}'", path.display());
+
+    if !tags.is_empty() {
+        metadata::update(&path, |meta| meta.tags = tags).await?;
+    }
+
+    if let Some(playlist) = playlist {
+        playlist::add_video(&playlist, &path).await?;
+        info!("added '{}' to playlist '{playlist}'", path.display());
+    }
+
+    processing::process(&path).await;
+
+    Ok(path)
+}
+
+// 'rain, lo-fi ,rain' -> ['rain', 'lo-fi']
+fn parse_tags(tags: &str) -> Vec<String> {
+    let mut parsed = Vec::<String>::new();
+    for tag in tags.split(',').map(str::trim).filter(|t| !t.is_empty()) {
+        if !parsed.iter().any(|t| t == tag) {
+            parsed.push(tag.to_string());
+        }
+    }
+
+    parsed
+}
+
 #[derive(Deserialize)]
 struct SwitchVideo {
     video_name: String,
@@ -87,17 +268,26 @@ async fn switch_video(
         visualizer,
     }): Json<SwitchVideo>,
 ) -> WebResult {
-    let video = video_path(&video_name);
+    let video = library::resolve(&video_name)?;
     if !video.is_file() {
         return Err(anyhow!("video not found").into());
     }
 
-    info!("switching video to '{}'", video.display());
+    storage::played(std::slice::from_ref(&video)).await;
+    let file_path = transcode::playback_path(&video).await;
+    // evens out loudness between videos, on top of whatever gain the user picked
+    let gain = gain * loudness::gain_factor(&metadata::get(&video).await);
+
+    info!(
+        "switching video to '{}' with gain {gain:.2}",
+        file_path.display()
+    );
     vlc.send(VlcMessage::ChangeVideo {
-        file_path: video,
+        file_path,
         gain,
         visualizer,
-        shuffle: false,
+        mode: PlaybackMode::InOrder,
+        items: HashMap::new(),
     })
     .context("failed to send message to vlc thread")?;
 
@@ -108,178 +298,624 @@ async fn stop_video(State(AppState { vlc, .. }): State<AppState>) -> WebResult {
     vlc.send(VlcMessage::StopVideo).map_err(Into::into)
 }
 
-async fn delete_video(Json(VideoName { video_name }): Json<VideoName>) -> WebResult {
-    let video_path = video_path(&video_name);
-    let _ = fs::remove_file(thumbnail_path(&video_path)).await;
+// goes to the trash, the response has the id to restore it with
+async fn delete_video(
+    Json(VideoName { video_name }): Json<VideoName>,
+) -> WebResult<Json<TrashItem>> {
+    let video_path = library::resolve(&video_name)?;
+    if !video_path.is_file() {
+        return Err(StatusError::new(StatusCode::NOT_FOUND, "video not found").into());
+    }
 
-    info!("deleting video '{}'", video_path.display());
+    library::ensure_writable(&video_path)?;
 
-    for mut playlist in playlist::playlists().await? {
-        if !playlist.videos.iter().any(|v| *v == video_path) {
-            continue;
-        }
+    info!("deleting video '{}'", video_path.display());
+    Ok(Json(trash::trash(&video_path).await?))
+}
 
-        info!("removing video from playlist '{}'", playlist.path.display());
+#[derive(Deserialize)]
+struct RenameVideo {
+    video_name: String,
+    new_name: String,
+}
 
-        playlist.videos.retain(|v| v != &video_path);
-        let _ = playlist::write_playlist(&playlist).await;
+async fn rename_video(
+    Json(RenameVideo {
+        video_name,
+        new_name,
+    }): Json<RenameVideo>,
+) -> WebResult {
+    let (old_path, new_path) = (library::resolve(&video_name)?, library::resolve(&new_name)?);
+    if !old_path.is_file() {
+        return Err(StatusError::new(StatusCode::NOT_FOUND, "video not found").into());
     }
 
-    info!("deleted video");
+    ensure_same_root(&old_path, &new_path)?;
+
+    if new_path.exists() {
+        return Err(StatusError::new(
+            StatusCode::CONFLICT,
+            format!("a video named '{new_name}' already exists"),
+        )
+        .into());
+    }
 
-    fs::remove_file(video_path).await.map_err(Into::into)
+    library::rename(&old_path, &new_path).await?;
+    Ok(())
 }
 
 #[derive(Serialize)]
 struct VideoInfo {
     size: u64,
+    // relative to the uploads folder, e.x. 'ambient/rain.mp4' or 'nas:ambient/rain.mp4'
     name: String,
     name_without_ext: String,
+    // e.x. 'ambient', empty if in the root, 'nas:' for the root of another library root
+    folder: String,
+    // none for uploads/
+    root: Option<String>,
+    read_only: bool,
+    thumbnail: String,
+    tags: Vec<String>,
+    // seconds, none until it's been probed
+    duration: Option<f64>,
+    // none if there's no playback friendly version (yet)
+    optimized: Option<Optimized>,
+    // lufs, none until measured
+    loudness: Option<f64>,
+    // if there's a sprite sheet, see /videos/preview
+    preview: bool,
+    // the last decode check failed, see /maintenance/decode
+    corrupt: bool,
 }
 
 async fn videos() -> WebResult<Json<Vec<VideoInfo>>> {
-    let files = ReadDirStream::new(fs::read_dir(VIDEO_PATH).await?)
-        .into_stream()
-        .try_filter_map(|entry| async move {
-            let file_type = entry.file_type().await?;
-            if !file_type.is_file() {
-                return Ok(None);
-            }
+    let mut files = vec![];
+    for path in library::walk_all().await?.files {
+        let Some(name) = video_name(&path) else {
+            continue;
+        };
+        let Some((root, _)) = library::split_root(&path) else {
+            continue;
+        };
 
-            let metadata = entry.metadata().await?;
-            let Ok(name) = entry.file_name().into_string() else {
-                return Ok(None);
-            };
+        let name_without_ext = path
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string();
 
-            let name_without_ext = entry
-                .path()
-                .file_stem()
-                .unwrap_or_default()
-                .to_string_lossy()
-                .to_string();
-
-            let size = metadata.len();
-            Ok(Some(VideoInfo {
-                size,
-                name,
-                name_without_ext,
-            }))
-        })
-        .collect::<Result<Vec<VideoInfo>, std::io::Error>>()
-        .await?;
+        let folder = path.parent().and_then(video_name).unwrap_or_default();
+
+        // changes whenever it's regenerated so browsers don't keep showing the old one
+        let thumbnail_path = thumbnail_path(&path);
+        let version = fs::metadata(&thumbnail_path)
+            .await
+            .and_then(|m| m.modified())
+            .map(|modified| {
+                let secs = modified.duration_since(UNIX_EPOCH).unwrap_or_default();
+                format!("?v={}", secs.as_secs())
+            })
+            .unwrap_or_default();
+        let thumbnail = format!("{}{version}", web_util::url_path(&thumbnail_path));
+
+        let meta = metadata::get(&path).await;
+        files.push(VideoInfo {
+            size: fs::metadata(&path).await?.len(),
+            tags: meta.tags,
+            duration: meta.probe.and_then(|p| p.duration),
+            optimized: meta.optimized,
+            loudness: meta.loudness,
+            preview: meta.preview.is_some(),
+            corrupt: meta.decode_check.is_some_and(|check| check.error.is_some()),
+            root: Some(root.name.clone()).filter(|name| !name.is_empty()),
+            read_only: root.read_only,
+            name,
+            name_without_ext,
+            folder,
+            thumbnail,
+        });
+    }
 
     Ok(Json(files))
 }
 
+async fn folders() -> WebResult<Json<Vec<String>>> {
+    let folders = library::walk_all()
+        .await?
+        .folders
+        .iter()
+        .filter_map(|f| video_name(f))
+        .collect();
+
+    Ok(Json(folders))
+}
+
+#[derive(Deserialize)]
+struct FolderName {
+    folder: String,
+}
+
+async fn create_folder(Json(FolderName { folder }): Json<FolderName>) -> WebResult {
+    let path = library::resolve(&folder)?;
+    if path.exists() {
+        return Err(StatusError::new(StatusCode::CONFLICT, "folder already exists").into());
+    }
+
+    library::ensure_writable(&path)?;
+
+    info!("creating folder '{}'", path.display());
+    fs::create_dir_all(path).await.map_err(Into::into)
+}
+
+#[derive(Deserialize)]
+struct MoveFolder {
+    folder: String,
+    new_folder: String,
+}
+
+async fn move_folder(Json(MoveFolder { folder, new_folder }): Json<MoveFolder>) -> WebResult {
+    let (old_path, new_path) = (library::resolve(&folder)?, library::resolve(&new_folder)?);
+    if !old_path.is_dir() {
+        return Err(StatusError::new(StatusCode::NOT_FOUND, "folder not found").into());
+    }
+
+    ensure_same_root(&old_path, &new_path)?;
+
+    if new_path.exists() {
+        return Err(StatusError::new(StatusCode::CONFLICT, "destination already exists").into());
+    }
+
+    if new_path.starts_with(&old_path) {
+        return Err(
+            StatusError::new(StatusCode::BAD_REQUEST, "can't move a folder into itself").into(),
+        );
+    }
+
+    info!(
+        "moving folder '{}' -> '{}'",
+        old_path.display(),
+        new_path.display()
+    );
+
+    library::create_parent(&new_path).await?;
+    fs::rename(&old_path, &new_path)
+        .await
+        .context("failed to move folder")?;
+
+    // thumbs, previews etc mirror the library so they move the same way
+    for mirror in library::MIRRORS {
+        let (old_mirror, new_mirror) = (
+            Path::new(mirror).join(library::mirror_relative(&old_path)),
+            Path::new(mirror).join(library::mirror_relative(&new_path)),
+        );
+
+        if old_mirror.is_dir() {
+            library::create_parent(&new_mirror).await?;
+            if let Err(e) = fs::rename(&old_mirror, &new_mirror).await {
+                warn!("failed to move '{mirror}', they will be regenerated: {e}");
+            }
+        }
+    }
+
+    let changed = playlist::replace_folder(&old_path, Some(&new_path)).await?;
+    metadata::rename_folder(&old_path, &new_path).await?;
+    info!("moved folder, updated {changed} playlists");
+
+    Ok(())
+}
+
+#[derive(Deserialize)]
+struct DeleteFolder {
+    folder: String,
+    // delete everything inside too
+    #[serde(default)]
+    recursive: bool,
+}
+
+async fn delete_folder(Json(DeleteFolder { folder, recursive }): Json<DeleteFolder>) -> WebResult {
+    let path = library::resolve(&folder)?;
+    if !path.is_dir() {
+        return Err(StatusError::new(StatusCode::NOT_FOUND, "folder not found").into());
+    }
+
+    library::ensure_writable(&path)?;
+
+    if !recursive {
+        info!("deleting empty folder '{}'", path.display());
+        return fs::remove_dir(&path)
+            .await
+            .map_err(|_| StatusError::new(StatusCode::CONFLICT, "folder isn't empty").into());
+    }
+
+    // only videos can go to the trash, anything else would be gone for good
+    let unlisted = library::unlisted_files(&path).await?;
+    if let Some(first) = unlisted.first() {
+        return Err(StatusError::new(
+            StatusCode::CONFLICT,
+            format!(
+                "folder has {} files that aren't videos, e.x. '{}', remove them first",
+                unlisted.len(),
+                first.display()
+            ),
+        )
+        .into());
+    }
+
+    info!("deleting folder '{}' and everything in it", path.display());
+
+    // every video goes to the trash on its own so they can be restored one by one
+    for video in library::walk(&path).await?.files {
+        trash::trash(&video).await?;
+    }
+
+    library::remove_generated_folder(&path).await;
+    let _ = metadata::remove_folder(&path).await;
+
+    // just empty folders left by now
+    fs::remove_dir_all(path).await.map_err(Into::into)
+}
+
+// renames & moves only happen inside of a root, the files would have to be copied otherwise
+fn ensure_same_root(from: &Path, to: &Path) -> Result<(), StatusError> {
+    library::ensure_writable(from)?;
+    library::ensure_writable(to)?;
+
+    let root_name = |path| library::split_root(path).map(|(root, _)| &root.name);
+    if root_name(from) != root_name(to) {
+        return Err(StatusError::new(
+            StatusCode::BAD_REQUEST,
+            "can't move between library roots",
+        ));
+    }
+
+    Ok(())
+}
+
 #[derive(Serialize)]
 struct PlaylistResponse {
-    // name without ext or path
+    // file name without the ext, e.x. 'lo-fi-beats'
+    id: String,
+    // whatever it was saved as, e.x. 'lo_fi beats'
     name: String,
+    description: Option<String>,
     // name as seen above for videos
     videos: Vec<String>,
+    mode: Option<PlaybackMode>,
+}
+
+impl From<Playlist> for PlaylistResponse {
+    fn from(p: Playlist) -> Self {
+        Self {
+            id: p.id(),
+            videos: p.videos.iter().filter_map(|v| video_name(v)).collect(),
+            name: p.name,
+            description: p.description,
+            mode: p.mode,
+        }
+    }
 }
 
 async fn playlists() -> WebResult<Json<Vec<PlaylistResponse>>> {
     let files = playlist::playlists()
         .await?
         .into_iter()
-        .map(|p| PlaylistResponse {
-            // ugh
-            name: p
-                .path
-                .file_stem()
-                .unwrap_or_default()
-                .to_string_lossy()
-                .into_owned(),
-            videos: p
-                .videos
-                .iter()
-                .filter_map(|v| Some(v.file_name()?.to_string_lossy().to_string()))
-                .collect(),
-        })
+        .map(PlaylistResponse::from)
         .collect();
 
     Ok(Json(files))
 }
 
-#[inline]
-fn playlist_name_to_file(name: &str) -> String {
-    format!("{}.vlc", name.replace(' ', "_"))
+// 404s instead of reading a playlist that isn't there
+async fn existing_playlist(id: &str) -> WebResult<Playlist> {
+    playlist::by_id(id).await?.ok_or_else(|| {
+        StatusError::new(StatusCode::NOT_FOUND, format!("playlist '{id}' not found")).into()
+    })
+}
+
+async fn delete_playlist(extract::Path(id): extract::Path<String>) -> WebResult {
+    let playlist = existing_playlist(&id).await?;
+    fs::remove_file(&playlist.path).await?;
+    info!("deleted playlist '{}'", playlist.name);
+
+    Ok(())
+}
+
+#[derive(Deserialize)]
+struct UpdatePlaylist {
+    new_name: Option<String>,
+    // empty clears it
+    description: Option<String>,
+    // for when another playlist already has new_name, rename picks 'new_name (1)'
+    #[serde(default)]
+    conflict: ConflictPolicy,
+}
+
+// the id stays the same so links to it keep working
+async fn update_playlist(
+    extract::Path(id): extract::Path<String>,
+    Json(UpdatePlaylist {
+        new_name,
+        description,
+        conflict,
+    }): Json<UpdatePlaylist>,
+) -> WebResult<Json<PlaylistResponse>> {
+    let mut playlist = existing_playlist(&id).await?;
+
+    if let Some(new_name) = new_name.filter(|n| *n != playlist.name) {
+        let new_name = playlist::claim_name(&new_name, conflict, Some(&playlist.path)).await?;
+        info!("renamed playlist '{}' to '{new_name}'", playlist.name);
+        playlist.name = new_name;
+    }
+
+    if let Some(description) = description {
+        playlist.description = Some(description).filter(|d| !d.is_empty());
+    }
+
+    playlist::write_playlist(&playlist).await?;
+    Ok(Json(playlist.into()))
+}
+
+#[derive(Deserialize)]
+struct InsertEntry {
+    video_name: String,
+    // the end if none, past the end is the end too
+    position: Option<usize>,
+}
+
+async fn insert_entry(
+    extract::Path(id): extract::Path<String>,
+    Json(InsertEntry {
+        video_name,
+        position,
+    }): Json<InsertEntry>,
+) -> WebResult<Json<PlaylistResponse>> {
+    let mut playlist = existing_playlist(&id).await?;
+    let video = library::resolve(&video_name)?;
+    if !video.is_file() {
+        return Err(StatusError::new(StatusCode::NOT_FOUND, "video not found").into());
+    }
+
+    // a playlist has every video at most once
+    if playlist.videos.contains(&video) {
+        return Err(StatusError::new(
+            StatusCode::CONFLICT,
+            format!("'{video_name}' is already in playlist '{}'", playlist.name),
+        )
+        .into());
+    }
+
+    let position = position.map_or(playlist.videos.len(), |p| p.min(playlist.videos.len()));
+    playlist.videos.insert(position, video);
+    playlist::write_playlist(&playlist).await?;
+    info!(
+        "added '{video_name}' to playlist '{}' at {position}",
+        playlist.name
+    );
+
+    Ok(Json(playlist.into()))
+}
+
+fn check_index(playlist: &Playlist, index: usize) -> Result<(), StatusError> {
+    if index < playlist.videos.len() {
+        return Ok(());
+    }
+
+    Err(StatusError::new(
+        StatusCode::NOT_FOUND,
+        format!(
+            "no entry {index}, the playlist has {}",
+            playlist.videos.len()
+        ),
+    ))
+}
+
+#[derive(Deserialize)]
+struct MoveEntry {
+    to: usize,
+}
+
+async fn move_entry(
+    extract::Path((id, index)): extract::Path<(String, usize)>,
+    Json(MoveEntry { to }): Json<MoveEntry>,
+) -> WebResult<Json<PlaylistResponse>> {
+    let mut playlist = existing_playlist(&id).await?;
+    check_index(&playlist, index)?;
+    check_index(&playlist, to)?;
+
+    let video = playlist.videos.remove(index);
+    playlist.videos.insert(to, video);
+    playlist::write_playlist(&playlist).await?;
+    info!(
+        "moved entry {index} of playlist '{}' to {to}",
+        playlist.name
+    );
+
+    Ok(Json(playlist.into()))
+}
+
+// removing the last entry removes the playlist, same as saving it empty
+async fn remove_entry(
+    extract::Path((id, index)): extract::Path<(String, usize)>,
+) -> WebResult<Json<PlaylistResponse>> {
+    let mut playlist = existing_playlist(&id).await?;
+    check_index(&playlist, index)?;
+
+    let video = playlist.videos.remove(index);
+    playlist::write_playlist(&playlist).await?;
+    info!(
+        "removed '{}' from playlist '{}'",
+        video.display(),
+        playlist.name
+    );
+
+    Ok(Json(playlist.into()))
 }
 
 #[derive(Deserialize)]
 struct SavePlaylist {
+    // updates this one, otherwise the one named playlist_name or a new one
+    playlist_id: Option<String>,
     // e.x. 'frank ocean'
     playlist_name: String,
     // e.x. ['frank_ocean.mp4']
     videos: Vec<String>,
+    // these keep whatever the playlist had if none, an empty description clears it
+    description: Option<String>,
+    mode: Option<PlaybackMode>,
 }
 
 async fn save_playlist(
     Json(SavePlaylist {
+        playlist_id,
         playlist_name,
         videos,
+        description,
+        mode,
     }): Json<SavePlaylist>,
-) -> WebResult {
-    let processed_name = playlist_name_to_file(&playlist_name);
-    let video_length = videos.len();
+) -> WebResult<Json<PlaylistResponse>> {
+    playlist::validate_name(&playlist_name)?;
+    let videos = videos
+        .iter()
+        .map(|v| library::resolve(v))
+        .collect::<Result<Vec<_>, _>>()?;
 
-    let playlist = Playlist::new(&processed_name, videos);
+    let existing = match &playlist_id {
+        Some(id) => Some(existing_playlist(id).await?),
+        None => playlist::named(&playlist_name, None).await?,
+    };
+
+    let mut playlist = match existing {
+        Some(mut playlist) => {
+            if playlist.name != playlist_name {
+                playlist.name = playlist::claim_name(
+                    &playlist_name,
+                    ConflictPolicy::Reject,
+                    Some(&playlist.path),
+                )
+                .await?;
+            }
+
+            playlist.videos = videos;
+            playlist
+        }
+        None => Playlist::new(playlist_name, videos),
+    };
+
+    if let Some(description) = description {
+        playlist.description = Some(description).filter(|d| !d.is_empty());
+    }
+
+    if mode.is_some() {
+        playlist.mode = mode;
+    }
 
     info!(
-        "saved playlist '{}' with {video_length} videos",
-        playlist_name,
+        "saved playlist '{}' ({}) with {} videos",
+        playlist.name,
+        playlist.id(),
+        playlist.videos.len()
     );
 
-    playlist::write_playlist(&playlist)
-        .await
-        .map_err(Into::into)
+    playlist::write_playlist(&playlist).await?;
+    Ok(Json(playlist.into()))
 }
 
 #[derive(Deserialize)]
 struct PlayPlaylist {
+    playlist_id: Option<String>,
+    // by name like from before ids, used if there's no id
     playlist_name: Option<String>,
+    // shuffles just this folder when there's no playlist
+    folder: Option<String>,
     #[serde(default)]
     gain: f32,
     visualizer: Option<String>,
+    // overrides the playlist's own mode, shuffle if neither says
+    mode: Option<PlaybackMode>,
 }
 
 async fn play_playlist(
     State(AppState { vlc, .. }): State<AppState>,
     Json(PlayPlaylist {
+        playlist_id,
         playlist_name,
+        folder,
         gain,
         visualizer,
+        mode,
     }): Json<PlayPlaylist>,
 ) -> WebResult {
-    let Some(playlist_name) = playlist_name else {
-        info!("shuffling all videos");
-        let _ = vlc.send(VlcMessage::ChangeVideo {
-            gain,
-            visualizer,
-            file_path: Path::new(VIDEO_PATH).to_path_buf(),
-            shuffle: true,
-        });
+    if playlist_id.is_none() && playlist_name.is_none() {
+        let videos = if let Some(folder) = folder {
+            // 'nas:' shuffles everything in that root
+            let path = match folder.strip_suffix(':').and_then(library::root) {
+                Some(root) => root.path.clone(),
+                None => library::resolve(&folder)?,
+            };
+            if !path.is_dir() {
+                return Err(StatusError::new(StatusCode::NOT_FOUND, "folder not found").into());
+            }
 
-        return Ok(());
-    };
+            info!("playing folder '{}'", path.display());
+            library::walk(&path).await?.files
+        } else {
+            info!("playing all videos");
+            library::walk_all().await?.files
+        };
 
-    let file_path = playlist::playlist_path(&playlist_name_to_file(&playlist_name));
-    if !file_path.is_file() {
-        return Err(anyhow!("playlist not found").into());
+        if videos.is_empty() {
+            return Err(StatusError::new(StatusCode::NOT_FOUND, "no videos to play").into());
+        }
+
+        // walked ourselves instead of letting vlc do it so optimized versions get picked
+        let mode = mode.unwrap_or_default();
+        let now_playing = now_playing(videos, mode).await?;
+        return vlc
+            .send(VlcMessage::ChangeVideo {
+                gain,
+                visualizer,
+                file_path: now_playing.path,
+                mode,
+                items: now_playing.items,
+            })
+            .map_err(Into::into);
+    }
+
+    let playlist = playlist::by_id_or_name(playlist_id.as_deref(), playlist_name.as_deref())
+        .await?
+        .ok_or_else(|| StatusError::new(StatusCode::NOT_FOUND, "playlist not found"))?;
+    if playlist.videos.is_empty() {
+        return Err(StatusError::new(StatusCode::BAD_REQUEST, "playlist is empty").into());
     }
 
-    info!("playing playlist '{}'", playlist_name);
+    let mode = mode.or(playlist.mode).unwrap_or_default();
+    info!("playing playlist '{}' ({})", playlist.name, mode.as_str());
+
+    let now_playing = now_playing(playlist.videos, mode).await?;
 
     vlc.send(VlcMessage::ChangeVideo {
         gain,
         visualizer,
-        file_path,
-        shuffle: true,
+        file_path: now_playing.path,
+        mode,
+        items: now_playing.items,
     })
     .map_err(Into::into)
 }
 
+// shuffle_no_repeat gets its order picked here, vlc just plays it top to bottom
+async fn now_playing(
+    mut videos: Vec<PathBuf>,
+    mode: PlaybackMode,
+) -> anyhow::Result<playlist::NowPlaying> {
+    if mode == PlaybackMode::ShuffleNoRepeat {
+        playlist::shuffle(&mut videos);
+    }
+
+    playlist::now_playing(&videos).await
+}
+
 #[derive(Deserialize)]
 struct MediaControl {
     action: u8,
@@ -314,7 +950,9 @@ async fn play_media(
         visualizer,
     }): Json<PlayMedia>,
 ) -> WebResult {
-    // yt-dlp --quiet --no-warnings --get-url -f "best[vcodec!=none][acodec!=none]/best" https://www.twitch.tv/ex
+    downloads::validate_url(&url)?;
+
+    // yt-dlp --quiet --no-warnings --get-url -f "best[vcodec!=none][acodec!=none]/best" -- https://www.twitch.tv/ex
     info!("getting direct url to media from '{url}'");
     let yt_dlp_output = Command::new("yt-dlp")
         .arg("--quiet")
@@ -322,6 +960,8 @@ async fn play_media(
         .arg("--get-url")
         .arg("-f")
         .arg("best[vcodec!=none][acodec!=none]/best")
+        // a url starting with '-' would be read as an option otherwise
+        .arg("--")
         .arg(&url)
         .output()
         .await?;
@@ -341,3 +981,60 @@ async fn play_media(
     })
     .map_err(Into::into)
 }
+
+#[derive(Deserialize)]
+struct SaveMedia {
+    url: String,
+    // defaults to the media's title
+    name: Option<String>,
+    folder: Option<String>,
+    // raw yt-dlp format string, overrides max_height
+    format: Option<String>,
+    // e.x. 480 since the tv can't show more anyway
+    max_height: Option<u32>,
+    playlist_name: Option<String>,
+    // unlike uploads this defaults to rename, nobody wants a finished download thrown away
+    conflict: Option<ConflictPolicy>,
+}
+
+#[derive(Serialize)]
+struct SaveMediaResponse {
+    // job id, also used in the download events
+    id: String,
+}
+
+async fn save_media(
+    Json(SaveMedia {
+        url,
+        name,
+        folder,
+        format,
+        max_height,
+        playlist_name,
+        conflict,
+    }): Json<SaveMedia>,
+) -> WebResult<(StatusCode, Json<SaveMediaResponse>)> {
+    // check everything up front instead of after a long download
+    downloads::validate_url(&url)?;
+
+    if let Some(name) = &name {
+        SafePath::file_name(name)?;
+    }
+
+    let download = Download {
+        url,
+        name,
+        folder: folder.as_deref().map(SafePath::new).transpose()?,
+        format,
+        max_height,
+        playlist: playlist_name
+            .map(|name| playlist::validate_name(&name).map(|()| name))
+            .transpose()?,
+        conflict: conflict.unwrap_or(ConflictPolicy::Rename),
+    };
+
+    info!("saving '{}' to the library", download.url);
+    let id = jobs::enqueue(JobTask::Download(download)).await;
+
+    Ok((StatusCode::ACCEPTED, Json(SaveMediaResponse { id })))
+}
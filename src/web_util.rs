@@ -1,4 +1,7 @@
-use std::path::PathBuf;
+use std::{
+    fmt::{self, Display, Formatter},
+    path::Path,
+};
 
 use ::futures::pin_mut;
 use anyhow::{anyhow, Context};
@@ -9,42 +12,129 @@ use axum::{
     BoxError,
 };
 use futures_util::{Stream, TryStreamExt};
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
 use rust_embed::RustEmbed;
-use simplelog::warn;
-use tokio::{fs::File, io::BufWriter};
+use sha2::{Digest, Sha256};
+use simplelog::{info, warn};
+use tokio::{
+    fs::File,
+    io::{AsyncReadExt, AsyncWrite, AsyncWriteExt, BufWriter},
+};
 use tokio_util::io::StreamReader;
 
-use crate::video_path;
+use crate::duplicates;
+
+// what stays readable in a url path segment, the rest (e.x. '#', '?', '%') gets escaped
+const URL_SEGMENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+// thumbs/a #1.mp4.jpg -> /thumbs/a%20%231.mp4.jpg, for links to files served by ServeDir
+#[must_use]
+pub fn url_path(path: &Path) -> String {
+    let mut url = String::new();
+    for segment in path {
+        url.push('/');
+        url.extend(utf8_percent_encode(&segment.to_string_lossy(), URL_SEGMENT));
+    }
+
+    url
+}
+
+pub struct Written {
+    pub bytes: u64,
+    // hex, hashed on the way through so big uploads don't have to be read twice
+    pub sha256: String,
+}
 
-pub async fn stream_to_file<S, E>(path: &str, stream: S) -> anyhow::Result<PathBuf>
+// room is from storage::make_room, anything bigger is refused instead of filling the disk
+pub async fn stream_to_file<S, E>(path: &Path, stream: S, room: u64) -> anyhow::Result<Written>
 where
     S: Stream<Item = Result<Bytes, E>> + Send,
     E: Into<BoxError>,
 {
-    let path = video_path(path);
+    let file = File::create(path)
+        .await
+        .map_err(|_| anyhow!("failed to create file {}", path.display()))?;
+
+    let mut hasher = Sha256::new();
+    let stream = stream.inspect_ok(|bytes| hasher.update(bytes));
+    // one past so there's a way to tell it didn't fit
+    let bytes = stream_into(file, stream, room.saturating_add(1)).await?;
+    if bytes > room {
+        return Err(StatusError::new(
+            StatusCode::INSUFFICIENT_STORAGE,
+            "ran out of space partway through",
+        )
+        .into());
+    }
 
-    let mut file = BufWriter::new(
-        File::create(&path)
-            .await
-            .map_err(|_| anyhow!("failed to create file {path:?}"))?,
-    );
+    Ok(Written {
+        bytes,
+        sha256: duplicates::hex(&hasher.finalize()),
+    })
+}
+
+// copies at most `limit` bytes of the stream into the writer
+pub async fn stream_into<W, S, E>(writer: W, stream: S, limit: u64) -> anyhow::Result<u64>
+where
+    W: AsyncWrite + Unpin,
+    S: Stream<Item = Result<Bytes, E>> + Send,
+    E: Into<BoxError>,
+{
+    let mut writer = BufWriter::new(writer);
 
     let body_with_io_error = stream.map_err(std::io::Error::other);
-    let body_reader = StreamReader::new(body_with_io_error);
+    let body_reader = StreamReader::new(body_with_io_error).take(limit);
     pin_mut!(body_reader);
 
-    tokio::io::copy(&mut body_reader, &mut file)
+    let written = tokio::io::copy(&mut body_reader, &mut writer)
         .await
         .context("failed to copy body to file")?;
 
-    Ok(path)
+    writer.flush().await?;
+
+    Ok(written)
 }
 
+// errors that should reach the client with a real status code instead of a 500
+#[derive(Debug)]
+pub struct StatusError {
+    pub status: StatusCode,
+    pub message: String,
+}
+
+impl StatusError {
+    pub fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            message: message.into(),
+        }
+    }
+}
+
+impl Display for StatusError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for StatusError {}
+
+pub type WebResult<T = ()> = Result<T, AppError>;
+
 pub struct AppError(anyhow::Error);
 
 impl IntoResponse for AppError {
     #[inline]
     fn into_response(self) -> Response {
+        if let Some(StatusError { status, message }) = self.0.downcast_ref::<StatusError>() {
+            info!("rejected request ({status}) -> {message}");
+            return (*status, message.clone()).into_response();
+        }
+
         // this is dumb
         warn!("web error captured -> {:?}", self.0);
 